@@ -0,0 +1,394 @@
+// One-to-one assignment of matched pairs, so that every left row and every
+// right row is used at most once. `Optimal` keeps as many pairs as possible
+// and, among those, the smallest total cost. The pairs form a sparse
+// bipartite graph, so it is split into connected components and each one is
+// solved by shortest augmenting paths over its own pairs. Components with
+// more than `MAX_OPTIMAL_ROWS` rows are refused, as the solver's run time
+// grows with the square of their size.
+// `Greedy` repeatedly takes the cheapest remaining pair; because both sides
+// rank each other by the same cost, the result is a stable matching.
+
+use crate::block::Matches;
+use crate::dedupe::UnionFind;
+use crate::utils::subset_pairs;
+use anyhow::{anyhow, Result};
+use rustc_hash::{FxHashMap, FxHashSet};
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+
+// Most rows, from both tables, in one connected group of matches for
+// `Optimal`; a group this size takes some tens of seconds
+pub const MAX_OPTIMAL_ROWS: usize = 20_000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Assignment {
+    Optimal,
+    Greedy,
+}
+
+impl Assignment {
+    pub fn new(method: Option<&str>) -> Result<Option<Self>> {
+        match method {
+            None => Ok(None),
+            Some("optimal") | Some("hungarian") => Ok(Some(Assignment::Optimal)),
+            Some("greedy") | Some("stable") => Ok(Some(Assignment::Greedy)),
+            Some(other) => Err(anyhow!(
+                "Unknown assignment `{}`; use \"optimal\" or \"greedy\"",
+                other
+            )),
+        }
+    }
+
+    // Positions of the pairs to keep, in their original order. Lower costs
+    // are better; NaN costs rank after every other pair.
+    pub fn select(&self, idxs1: &[usize], idxs2: &[usize], costs: &[f64]) -> Result<Vec<usize>> {
+        let mut keep = match self {
+            Assignment::Optimal => optimal(idxs1, idxs2, costs)?,
+            Assignment::Greedy => greedy(idxs1, idxs2, costs),
+        };
+        keep.sort_unstable();
+        Ok(keep)
+    }
+}
+
+// Reduce matched pairs to a one-to-one assignment, with the distances in
+// `dists[cost_col]` as costs. Signed distances rank by their size.
+pub fn assign_matches(
+    matches: Matches,
+    cost_col: usize,
+    assignment: Option<Assignment>,
+) -> Result<Matches> {
+    let Some(assignment) = assignment else {
+        return Ok(matches);
+    };
+    let (idxs1, idxs2, dists) = matches;
+    let costs: Vec<f64> = dists[cost_col].iter().map(|d| d.abs()).collect();
+    let keep = assignment.select(&idxs1, &idxs2, &costs)?;
+    Ok(subset_pairs(&idxs1, &idxs2, &dists, &keep))
+}
+
+fn greedy(idxs1: &[usize], idxs2: &[usize], costs: &[f64]) -> Vec<usize> {
+    let mut order: Vec<usize> = (0..costs.len()).collect();
+    order.sort_by(|&a, &b| {
+        rank_cost(costs[a])
+            .total_cmp(&rank_cost(costs[b]))
+            .then((idxs1[a], idxs2[a]).cmp(&(idxs1[b], idxs2[b])))
+    });
+
+    let mut used1: FxHashSet<usize> = FxHashSet::default();
+    let mut used2: FxHashSet<usize> = FxHashSet::default();
+    order
+        .into_iter()
+        .filter(|&p| {
+            if used1.contains(&idxs1[p]) || used2.contains(&idxs2[p]) {
+                return false;
+            }
+            used1.insert(idxs1[p]);
+            used2.insert(idxs2[p]);
+            true
+        })
+        .collect()
+}
+
+fn rank_cost(cost: f64) -> f64 {
+    if cost.is_nan() {
+        f64::INFINITY
+    } else {
+        cost
+    }
+}
+
+fn optimal(idxs1: &[usize], idxs2: &[usize], costs: &[f64]) -> Result<Vec<usize>> {
+    // Compact node ids: left rows first, then right rows
+    let mut left: FxHashMap<usize, usize> = FxHashMap::default();
+    let mut right: FxHashMap<usize, usize> = FxHashMap::default();
+    for &i in idxs1 {
+        let n = left.len();
+        left.entry(i).or_insert(n);
+    }
+    for &j in idxs2 {
+        let n = right.len();
+        right.entry(j).or_insert(n);
+    }
+    let nleft = left.len();
+
+    let mut uf = UnionFind::new(nleft + right.len());
+    for (i, j) in idxs1.iter().zip(idxs2) {
+        uf.union(left[i], nleft + right[j]);
+    }
+
+    let mut components: FxHashMap<usize, Vec<usize>> = FxHashMap::default();
+    let mut sizes: FxHashMap<usize, usize> = FxHashMap::default();
+    for node in 0..nleft + right.len() {
+        *sizes.entry(uf.find(node)).or_default() += 1;
+    }
+    for (p, i) in idxs1.iter().enumerate() {
+        components.entry(uf.find(left[i])).or_default().push(p);
+    }
+
+    if let Some(&largest) = sizes.values().max() {
+        if largest > MAX_OPTIMAL_ROWS {
+            return Err(anyhow!(
+                "A group of {} connected rows is too large for `assign = \"optimal\"` \
+                 (at most {}); use `assign = \"greedy\"`, or split the matches with \
+                 `k`, `block_by` or a smaller `max_distance`",
+                largest,
+                MAX_OPTIMAL_ROWS
+            ));
+        }
+    }
+
+    Ok(components
+        .into_values()
+        .flat_map(|pairs| solve_component(&pairs, idxs1, idxs2, costs))
+        .collect())
+}
+
+// Minimum-cost maximum matching within one connected component, given the
+// positions of its pairs. Only the pairs themselves are stored, so a large
+// component costs memory in proportion to its pairs rather than to the
+// product of its row counts.
+fn solve_component(pairs: &[usize], idxs1: &[usize], idxs2: &[usize], costs: &[f64]) -> Vec<usize> {
+    if pairs.len() == 1 {
+        return pairs.to_vec();
+    }
+
+    let mut rows: FxHashMap<usize, usize> = FxHashMap::default();
+    let mut cols: FxHashMap<usize, usize> = FxHashMap::default();
+    for &p in pairs {
+        let n = rows.len();
+        rows.entry(idxs1[p]).or_insert(n);
+        let n = cols.len();
+        cols.entry(idxs2[p]).or_insert(n);
+    }
+
+    // Shift costs to start at zero, as the shortest path search needs
+    // non-negative costs
+    let low = pairs
+        .iter()
+        .map(|&p| costs[p])
+        .filter(|c| c.is_finite())
+        .fold(f64::INFINITY, f64::min);
+    let high = pairs
+        .iter()
+        .map(|&p| costs[p])
+        .filter(|c| c.is_finite())
+        .fold(f64::NEG_INFINITY, f64::max);
+    let (low, span) = if low.is_finite() {
+        (low, high - low)
+    } else {
+        (0.0, 0.0)
+    };
+    // NaN and infinite costs rank just behind the worst finite one
+    let fallback = span + 1.0;
+
+    let mut edges: Vec<Vec<Edge>> = vec![Vec::new(); rows.len()];
+    for &p in pairs {
+        let cost = if costs[p].is_finite() {
+            costs[p] - low
+        } else {
+            fallback
+        };
+        edges[rows[&idxs1[p]]].push(Edge {
+            col: cols[&idxs2[p]],
+            cost,
+            pair: p,
+        });
+    }
+
+    shortest_augmenting_paths(&edges, cols.len())
+}
+
+// A candidate pair between a left row and a right column of a component
+#[derive(Debug, Clone)]
+struct Edge {
+    col: usize,
+    cost: f64,
+    pair: usize,
+}
+
+// Heap entry for Dijkstra's algorithm, ordered so that `BinaryHeap` pops the
+// smallest distance first. Rows are nodes `0..nrow` and columns follow.
+#[derive(Debug, PartialEq)]
+struct State {
+    dist: f64,
+    node: usize,
+}
+
+impl Eq for State {}
+
+impl Ord for State {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .dist
+            .total_cmp(&self.dist)
+            .then(other.node.cmp(&self.node))
+    }
+}
+
+impl PartialOrd for State {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+// Minimum-cost maximum matching by successive shortest augmenting paths,
+// the sparse form of the Hungarian algorithm. Each round runs Dijkstra's
+// algorithm from every unmatched row over unmatched pairs forwards and
+// matched pairs backwards, with node potentials keeping the reduced costs
+// non-negative, and flips the cheapest path to an unmatched column. The
+// search stops as soon as that path is known and only the nodes it settled
+// are updated, so a round costs O(E log V) in the worst case but usually far
+// less. Returns the positions of the matched pairs.
+fn shortest_augmenting_paths(edges: &[Vec<Edge>], ncol: usize) -> Vec<usize> {
+    let nrow = edges.len();
+    // Edge of `edges[i]` matched to row i, and the row and edge matched to
+    // each column
+    let mut row_match: Vec<Option<usize>> = vec![None; nrow];
+    let mut col_match: Vec<Option<(usize, usize)>> = vec![None; ncol];
+
+    // Potentials of the rows, the columns and a source joined to every
+    // unmatched row. Every unmatched column leads to a sink whose potential
+    // stays at zero.
+    let mut pot_row = vec![0.0; nrow];
+    let mut pot_col = vec![0.0; ncol];
+    let mut pot_source = 0.0;
+
+    // Unmatched rows start at `pot_source - pot_row[i]`. The potential
+    // updates below keep them in the same order, so they are sorted once and
+    // merged into each search instead of filling the heap.
+    let mut free_rows: Vec<usize> = (0..nrow).collect();
+
+    // Tentative distances, reset after each round for the nodes it touched
+    let mut dist_row = vec![f64::INFINITY; nrow];
+    let mut dist_col = vec![f64::INFINITY; ncol];
+    let mut reached_by: Vec<Option<(usize, usize)>> = vec![None; ncol];
+
+    while !free_rows.is_empty() {
+        let mut heap: BinaryHeap<State> = BinaryHeap::new();
+        let mut next_free = 0;
+        let mut touched: Vec<usize> = Vec::new();
+        let mut settled: Vec<(usize, f64)> = Vec::new();
+
+        // Distance to the sink and the unmatched column it is reached from
+        let mut sink = f64::INFINITY;
+        let mut target = None;
+
+        loop {
+            let from_rows = free_rows.get(next_free).map(|&i| pot_source - pot_row[i]);
+            let from_heap = heap.peek().map(|state| state.dist);
+            let State { dist, node } = match (from_rows, from_heap) {
+                (Some(d), h) if h.map_or(true, |h| d <= h) => {
+                    let i = free_rows[next_free];
+                    next_free += 1;
+                    dist_row[i] = d;
+                    touched.push(i);
+                    State { dist: d, node: i }
+                }
+                (None, None) => break,
+                _ => heap.pop().expect("the heap is not empty"),
+            };
+            if dist >= sink {
+                break;
+            }
+
+            if node < nrow {
+                let i = node;
+                if dist > dist_row[i] {
+                    continue;
+                }
+                settled.push((node, dist));
+                for (e, edge) in edges[i].iter().enumerate() {
+                    if row_match[i] == Some(e) {
+                        continue;
+                    }
+                    // Rounding can leave reduced costs slightly negative
+                    let next = (dist + edge.cost + pot_row[i] - pot_col[edge.col]).max(dist);
+                    if next < dist_col[edge.col] {
+                        dist_col[edge.col] = next;
+                        reached_by[edge.col] = Some((i, e));
+                        touched.push(nrow + edge.col);
+                        heap.push(State {
+                            dist: next,
+                            node: nrow + edge.col,
+                        });
+                    }
+                }
+            } else {
+                let j = node - nrow;
+                if dist > dist_col[j] {
+                    continue;
+                }
+                settled.push((node, dist));
+                match col_match[j] {
+                    Some((i, e)) => {
+                        let next = (dist - edges[i][e].cost + pot_col[j] - pot_row[i]).max(dist);
+                        if next < dist_row[i] {
+                            dist_row[i] = next;
+                            touched.push(i);
+                            heap.push(State {
+                                dist: next,
+                                node: i,
+                            });
+                        }
+                    }
+                    None => {
+                        let next = (dist + pot_col[j]).max(dist);
+                        if next < sink {
+                            sink = next;
+                            target = Some(j);
+                        }
+                    }
+                }
+            }
+        }
+
+        // Flip the path back to the unmatched row it started from
+        if let Some(mut j) = target {
+            loop {
+                let (i, e) = reached_by[j].expect("reached columns record their row");
+                let previous = row_match[i].replace(e);
+                col_match[j] = Some((i, e));
+                match previous {
+                    Some(old) => j = edges[i][old].col,
+                    None => {
+                        free_rows.retain(|&r| r != i);
+                        break;
+                    }
+                }
+            }
+        }
+
+        for node in touched {
+            if node < nrow {
+                dist_row[node] = f64::INFINITY;
+            } else {
+                dist_col[node - nrow] = f64::INFINITY;
+                reached_by[node - nrow] = None;
+            }
+        }
+
+        // No unmatched row can reach an unmatched column any more
+        if target.is_none() {
+            break;
+        }
+
+        // Settled nodes move by their distance and everything else by the
+        // sink's, which keeps every reduced cost non-negative. Moving the
+        // source the other way instead leaves the rest untouched.
+        for (node, dist) in settled {
+            if node < nrow {
+                pot_row[node] += dist - sink;
+            } else {
+                pot_col[node - nrow] += dist - sink;
+            }
+        }
+        pot_source -= sink;
+    }
+
+    row_match
+        .iter()
+        .enumerate()
+        .filter_map(|(i, e)| e.map(|e| edges[i][e].pair))
+        .collect()
+}
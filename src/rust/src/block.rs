@@ -0,0 +1,225 @@
+// Exact-match blocking. Rows of both frames are partitioned on the values of
+// the `block_by` columns, and a join only compares rows within the same
+// block. Each block is sliced out of the join columns, matched on its own
+// (the kernels stay parallel within a block), and the block's row indices are
+// mapped back onto the original frames. Rows with a missing block key never
+// match.
+//
+// Blocks run one after another. The kernels read their keys from R vectors,
+// which may only be touched from the main thread, so blocks cannot be handed
+// to the pool as a whole. Each block pays for an R slice of every join column
+// and for building the kernel's index, which is negligible for a few large
+// blocks but dominates with many blocks of a handful of rows.
+
+use anyhow::{anyhow, Result};
+use extendr_api::prelude::*;
+use rustc_hash::FxHashMap;
+
+use crate::utils::subset_pairs;
+
+pub type Matches = (Vec<usize>, Vec<usize>, Vec<Vec<f64>>);
+
+pub struct Blocks {
+    // 1-based rows of df1 and df2 in each block, for blocks present in both
+    pub groups: Vec<(Vec<usize>, Vec<usize>)>,
+}
+
+impl Blocks {
+    // `block_by` follows the same layout as `by`: names are left-hand
+    // columns and values the matching right-hand columns
+    pub fn new(df1: &List, df2: &List, block_by: &List) -> Result<Self> {
+        let mut cols1 = Vec::with_capacity(block_by.len());
+        let mut cols2 = Vec::with_capacity(block_by.len());
+        for (left_key, val) in block_by.iter() {
+            let right_key = val
+                .as_str_vector()
+                .and_then(|x| x.first().copied())
+                .ok_or_else(|| anyhow!("Missing right block key for '{}'", left_key))?;
+            cols1.push(column_keys(df1, left_key)?);
+            cols2.push(column_keys(df2, right_key)?);
+        }
+
+        let keys1 = row_keys(&cols1);
+        let keys2 = row_keys(&cols2);
+
+        let mut rows2: FxHashMap<&str, Vec<usize>> = FxHashMap::default();
+        for (j, key) in keys2.iter().enumerate() {
+            if let Some(key) = key {
+                rows2.entry(key.as_str()).or_default().push(j + 1);
+            }
+        }
+
+        // Blocks are ordered by their first row in df1
+        let mut order: Vec<&str> = Vec::new();
+        let mut rows1: FxHashMap<&str, Vec<usize>> = FxHashMap::default();
+        for (i, key) in keys1.iter().enumerate() {
+            if let Some(key) = key {
+                if !rows2.contains_key(key.as_str()) {
+                    continue;
+                }
+                let rows = rows1.entry(key.as_str()).or_default();
+                if rows.is_empty() {
+                    order.push(key.as_str());
+                }
+                rows.push(i + 1);
+            }
+        }
+
+        let groups = order
+            .into_iter()
+            .map(|key| {
+                (
+                    rows1.remove(key).unwrap_or_default(),
+                    rows2.remove(key).unwrap_or_default(),
+                )
+            })
+            .collect();
+
+        Ok(Blocks { groups })
+    }
+
+    // Run `matcher` on each block in turn, restricted to the columns it
+    // needs, and return the matches in original row numbers, ordered by
+    // (left, right).
+    // `ndists` is the number of distance vectors `matcher` returns.
+    pub fn run<F>(
+        &self,
+        df1: &List,
+        df2: &List,
+        cols1: &[&str],
+        cols2: &[&str],
+        ndists: usize,
+        mut matcher: F,
+    ) -> Result<Matches>
+    where
+        F: FnMut(&List, &List) -> Result<Matches>,
+    {
+        let mut idxs1 = Vec::new();
+        let mut idxs2 = Vec::new();
+        let mut dists: Vec<Vec<f64>> = vec![Vec::new(); ndists];
+
+        for (rows1, rows2) in &self.groups {
+            let sub1 = subframe(df1, cols1, rows1)?;
+            let sub2 = subframe(df2, cols2, rows2)?;
+            let (local1, local2, local_dists) = matcher(&sub1, &sub2)?;
+
+            idxs1.extend(local1.iter().map(|&i| rows1[i - 1]));
+            idxs2.extend(local2.iter().map(|&j| rows2[j - 1]));
+            for (col, local) in dists.iter_mut().zip(local_dists) {
+                col.extend(local);
+            }
+        }
+
+        let mut order: Vec<usize> = (0..idxs1.len()).collect();
+        order.sort_by_key(|&p| (idxs1[p], idxs2[p]));
+        Ok(subset_pairs(&idxs1, &idxs2, &dists, &order))
+    }
+}
+
+// Run `matcher` per block when blocking is requested, otherwise on the
+// whole frames
+pub fn blocked_matches<F>(
+    df1: &List,
+    df2: &List,
+    block_by: Option<&List>,
+    cols1: &[&str],
+    cols2: &[&str],
+    ndists: usize,
+    matcher: F,
+) -> Result<Matches>
+where
+    F: FnMut(&List, &List) -> Result<Matches>,
+{
+    let mut matcher = matcher;
+    match block_by {
+        Some(block_by) => {
+            Blocks::new(df1, df2, block_by)?.run(df1, df2, cols1, cols2, ndists, matcher)
+        }
+        None => matcher(df1, df2),
+    }
+}
+
+// One key string per row of a column, `None` where the value is missing.
+// Factors are keyed on their labels so that codes from different frames
+// cannot collide.
+fn column_keys(df: &List, col: &str) -> Result<Vec<Option<String>>> {
+    let x = df
+        .dollar(col)
+        .map_err(|_| anyhow!("Missing block column '{}'", col))?;
+
+    if x.is_factor() {
+        let levels: Vec<&str> = x
+            .levels()
+            .ok_or_else(|| anyhow!("Block column '{}' has no levels", col))?
+            .collect();
+        let codes = x
+            .as_integer_slice()
+            .ok_or_else(|| anyhow!("Block column '{}' has invalid codes", col))?;
+        return Ok(codes
+            .iter()
+            .map(|&c| (!c.is_na()).then(|| levels[c as usize - 1].to_string()))
+            .collect());
+    }
+
+    let keys = match x.rtype() {
+        Rtype::Strings => Strings::try_from(&x)
+            .map_err(|_| anyhow!("Block column '{}' is not a string vector", col))?
+            .iter()
+            .map(|s| (!s.is_na()).then(|| s.to_string()))
+            .collect(),
+        Rtype::Integers => x
+            .as_integer_slice()
+            .unwrap_or_default()
+            .iter()
+            .map(|i| (!i.is_na()).then(|| i.to_string()))
+            .collect(),
+        // Adding zero folds -0 into 0
+        Rtype::Doubles => x
+            .as_real_slice()
+            .unwrap_or_default()
+            .iter()
+            .map(|v| (!v.is_nan()).then(|| (v + 0.0).to_string()))
+            .collect(),
+        Rtype::Logicals => x
+            .as_logical_slice()
+            .unwrap_or_default()
+            .iter()
+            .map(|b| (!b.is_na()).then(|| b.is_true().to_string()))
+            .collect(),
+        _ => {
+            return Err(anyhow!(
+                "Block column '{}' must be character, factor, numeric or logical",
+                col
+            ))
+        }
+    };
+    Ok(keys)
+}
+
+// Combine the column keys of each row into one, missing if any part is
+fn row_keys(cols: &[Vec<Option<String>>]) -> Vec<Option<String>> {
+    let nrows = cols.first().map_or(0, |c| c.len());
+    (0..nrows)
+        .map(|r| {
+            cols.iter()
+                .map(|c| c[r].as_deref())
+                .collect::<Option<Vec<&str>>>()
+                .map(|parts| parts.join("\u{1f}"))
+        })
+        .collect()
+}
+
+// The given columns of `df`, restricted to `rows` (1-based)
+fn subframe(df: &List, cols: &[&str], rows: &[usize]) -> Result<List> {
+    let values = cols
+        .iter()
+        .map(|col| {
+            df.dollar(col)
+                .map_err(|_| anyhow!("Missing column '{}'", col))?
+                .slice(rows)
+                .map_err(|_| anyhow!("Failed to slice column '{}'", col))
+        })
+        .collect::<Result<Vec<Robj>>>()?;
+    List::from_names_and_values(cols, values)
+        .map_err(|e| anyhow!("Failed to build block frame: {e}"))
+}
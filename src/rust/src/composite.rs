@@ -0,0 +1,103 @@
+// Weighted composite scores for multi-column joins. Each column's distance
+// is normalised to [0, 1] by dividing by that column's `max_distance`, so a
+// column contributes 1 once it reaches its own threshold, and pairs that
+// fail a column outright (e.g. different Soundex codes) also count as 1.
+// The score is the weighted mean of the normalised distances; pairs are kept
+// when it does not exceed `max_score`.
+
+use anyhow::{anyhow, Result};
+
+#[derive(Debug, Clone)]
+pub struct CompositeScore {
+    pub weights: Vec<f64>,
+    pub max_score: f64,
+}
+
+impl CompositeScore {
+    // Returns `None` when no weights were given, i.e. every column must pass
+    // its own threshold. `max_distances` holds each column's threshold, which
+    // must be finite for the normalised distances to mean anything.
+    pub fn new(
+        weights: Option<Vec<f64>>,
+        max_score: Option<f64>,
+        max_distances: &[f64],
+    ) -> Result<Option<Self>> {
+        let Some(weights) = weights else {
+            return Ok(None);
+        };
+
+        let ncols = max_distances.len();
+        if weights.len() != ncols {
+            return Err(anyhow!(
+                "Expected {} weights (one per join column), got {}",
+                ncols,
+                weights.len()
+            ));
+        }
+        if weights.iter().any(|w| !w.is_finite() || *w < 0.0) {
+            return Err(anyhow!("`weights` must be finite and non-negative"));
+        }
+        if weights.iter().sum::<f64>() <= 0.0 {
+            return Err(anyhow!("At least one weight must be positive"));
+        }
+        if max_distances.iter().any(|m| !m.is_finite()) {
+            return Err(anyhow!(
+                "`weights` need a finite `max_distance` for every join column"
+            ));
+        }
+
+        let max_score =
+            max_score.ok_or_else(|| anyhow!("Must provide `max_score` with `weights`"))?;
+        if max_score.is_nan() {
+            return Err(anyhow!("`max_score` must not be NA"));
+        }
+
+        Ok(Some(CompositeScore { weights, max_score }))
+    }
+
+    // Map a raw distance onto [0, 1]. `NA` (or any NaN) marks a pair that
+    // failed the column's own comparison.
+    pub fn normalise(dist: f64, max_distance: f64) -> f64 {
+        if dist.is_nan() {
+            1.0
+        } else if max_distance <= 0.0 {
+            // Nothing to scale by: only exact matches score 0
+            if dist <= 0.0 {
+                0.0
+            } else {
+                1.0
+            }
+        } else {
+            (dist / max_distance).min(1.0)
+        }
+    }
+
+    // Weighted mean of the normalised distances of every pair. `dists` holds
+    // one vector per column and `max_distances` the matching thresholds.
+    pub fn scores(&self, dists: &[Vec<f64>], max_distances: &[f64]) -> Vec<f64> {
+        let total: f64 = self.weights.iter().sum();
+        let npairs = dists.first().map_or(0, |d| d.len());
+
+        (0..npairs)
+            .map(|p| {
+                dists
+                    .iter()
+                    .zip(max_distances)
+                    .zip(&self.weights)
+                    .map(|((d, m), w)| w * Self::normalise(d[p], *m))
+                    .sum::<f64>()
+                    / total
+            })
+            .collect()
+    }
+
+    // Positions of the pairs whose score passes `max_score`
+    pub fn passing(&self, scores: &[f64]) -> Vec<usize> {
+        scores
+            .iter()
+            .enumerate()
+            .filter(|(_, s)| **s <= self.max_score)
+            .map(|(p, _)| p)
+            .collect()
+    }
+}
@@ -0,0 +1,112 @@
+// Self-join deduplication. A table is matched against itself with the
+// kernels in self-join mode, so each pair of rows is compared once and found
+// as (i, j) with i < j, and the matched pairs are then merged into clusters
+// with union-find. Every input row gets a cluster ID, singletons included.
+
+use itertools::iproduct;
+
+// How a kernel pairs up left and right rows. In a self-join both sides are
+// the same table: each row is only compared with later rows, and each
+// distinct key only with itself and the keys that sort after it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Pairing {
+    #[default]
+    Cross,
+    SelfJoin,
+}
+
+impl Pairing {
+    // Whether rows `i` and `j` (0- or 1-based alike) should be compared
+    pub fn compares_rows(self, i: usize, j: usize) -> bool {
+        self == Pairing::Cross || i < j
+    }
+
+    // Whether distinct keys `k1` and `k2` should be compared
+    pub fn compares_keys(self, k1: &str, k2: &str) -> bool {
+        self == Pairing::Cross || k1 <= k2
+    }
+
+    // Row pairs of a left key with rows `v1` and a right key with rows `v2`.
+    // In a self-join, pairs within one key are emitted once and pairs across
+    // keys are ordered so the smaller row comes first.
+    pub fn key_rows<'a>(
+        self,
+        v1: &'a [usize],
+        v2: &'a [usize],
+        dist: f64,
+    ) -> impl Iterator<Item = (usize, usize, f64)> + 'a {
+        // Distinct keys have disjoint rows, so equal first rows mean one key
+        let same_key = v1.first() == v2.first();
+        iproduct!(v1, v2).filter_map(move |(&a, &b)| match self {
+            Pairing::Cross => Some((a, b, dist)),
+            Pairing::SelfJoin if same_key => (a < b).then_some((a, b, dist)),
+            Pairing::SelfJoin => Some((a.min(b), a.max(b), dist)),
+        })
+    }
+}
+
+pub struct UnionFind {
+    parent: Vec<usize>,
+    rank: Vec<u8>,
+}
+
+impl UnionFind {
+    pub fn new(n: usize) -> Self {
+        UnionFind {
+            parent: (0..n).collect(),
+            rank: vec![0; n],
+        }
+    }
+
+    pub fn find(&mut self, x: usize) -> usize {
+        let mut root = x;
+        while self.parent[root] != root {
+            root = self.parent[root];
+        }
+        // Path compression
+        let mut x = x;
+        while self.parent[x] != root {
+            let next = self.parent[x];
+            self.parent[x] = root;
+            x = next;
+        }
+        root
+    }
+
+    pub fn union(&mut self, a: usize, b: usize) {
+        let (a, b) = (self.find(a), self.find(b));
+        if a == b {
+            return;
+        }
+        match self.rank[a].cmp(&self.rank[b]) {
+            std::cmp::Ordering::Less => self.parent[a] = b,
+            std::cmp::Ordering::Greater => self.parent[b] = a,
+            std::cmp::Ordering::Equal => {
+                self.parent[b] = a;
+                self.rank[a] += 1;
+            }
+        }
+    }
+}
+
+// Cluster ID of each of `nrows` rows given 1-based matched pairs. IDs start
+// at 1 and are numbered in order of each cluster's first row.
+pub fn cluster_ids(nrows: usize, idxs1: &[usize], idxs2: &[usize]) -> Vec<i32> {
+    let mut uf = UnionFind::new(nrows);
+    for (i, j) in idxs1.iter().zip(idxs2) {
+        uf.union(i - 1, j - 1);
+    }
+
+    let mut ids = vec![0; nrows];
+    let mut root_ids = vec![0; nrows];
+    let mut next = 0;
+    for (row, id) in ids.iter_mut().enumerate() {
+        let root = uf.find(row);
+        if root_ids[root] == 0 {
+            next += 1;
+            root_ids[root] = next;
+        }
+        *id = root_ids[root];
+    }
+    ids
+}
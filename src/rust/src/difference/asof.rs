@@ -0,0 +1,110 @@
+// As-of (rolling) join. Each left value is matched to at most one right row:
+// the closest one at or before it (`Backward`), at or after it (`Forward`),
+// or on either side (`Nearest`), optionally within a tolerance. Right values
+// are sorted once per exact-match group and every left value is found with a
+// binary search.
+
+use crate::block::Matches;
+use anyhow::{anyhow, Result};
+use itertools::MultiUnzip;
+use rayon::prelude::*;
+use rayon::ThreadPool;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Direction {
+    Backward,
+    Forward,
+    Nearest,
+}
+
+impl Direction {
+    pub fn new(direction: &str) -> Result<Self> {
+        match direction {
+            "backward" => Ok(Direction::Backward),
+            "forward" => Ok(Direction::Forward),
+            "nearest" => Ok(Direction::Nearest),
+            other => Err(anyhow!(
+                "Unknown direction `{}`; use \"backward\", \"forward\" or \"nearest\"",
+                other
+            )),
+        }
+    }
+}
+
+// Right values of one group sorted ascending, with their 1-based rows. Among
+// equal values the later row sorts last.
+fn sorted_group(vec2: &[f64], rows2: &[usize]) -> Vec<(f64, usize)> {
+    let mut sorted: Vec<(f64, usize)> = rows2
+        .iter()
+        .map(|&j| (vec2[j - 1], j))
+        .filter(|(y, _)| !y.is_nan())
+        .collect();
+    sorted.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1)));
+    sorted
+}
+
+// The matching right row for `x` and its absolute difference. Backward
+// prefers the latest of several equal right values and forward the
+// earliest; nearest breaks distance ties towards the earlier value.
+fn roll(sorted: &[(f64, usize)], x: f64, direction: Direction) -> Option<(usize, f64)> {
+    let before = || {
+        let pos = sorted.partition_point(|(y, _)| *y <= x);
+        (pos > 0).then(|| (sorted[pos - 1].1, x - sorted[pos - 1].0))
+    };
+    let after = || {
+        let pos = sorted.partition_point(|(y, _)| *y < x);
+        (pos < sorted.len()).then(|| (sorted[pos].1, sorted[pos].0 - x))
+    };
+
+    match direction {
+        Direction::Backward => before(),
+        Direction::Forward => after(),
+        Direction::Nearest => match (before(), after()) {
+            (Some(b), Some(a)) => Some(if b.1 <= a.1 { b } else { a }),
+            (b, a) => b.or(a),
+        },
+    }
+}
+
+// One match per left row at most, ordered by left row. Groups and the left
+// rows within each group are searched in parallel. `groups` holds the
+// 1-based rows of each exact-match group on both sides; rows outside every
+// group never match.
+pub fn asof_matches(
+    vec1: &[f64],
+    vec2: &[f64],
+    groups: &[(Vec<usize>, Vec<usize>)],
+    direction: Direction,
+    tolerance: Option<f64>,
+    pool: &ThreadPool,
+) -> Matches {
+    let threshold = tolerance.map_or(f64::INFINITY, |t| t + f64::EPSILON);
+
+    let mut triples: Vec<(usize, usize, f64)> = pool.install(|| {
+        let sorted: Vec<Vec<(f64, usize)>> = groups
+            .par_iter()
+            .map(|(_, rows2)| sorted_group(vec2, rows2))
+            .collect();
+
+        groups
+            .par_iter()
+            .zip(&sorted)
+            .flat_map(|((rows1, _), sorted)| {
+                rows1.par_iter().filter_map(move |&i| {
+                    let x = vec1[i - 1];
+                    if x.is_nan() {
+                        return None;
+                    }
+                    roll(sorted, x, direction)
+                        .filter(|&(_, diff)| diff <= threshold)
+                        .map(|(j, diff)| (i, j, diff))
+                })
+            })
+            .collect()
+    });
+
+    triples.sort_unstable_by_key(|&(i, _, _)| i);
+    let (idxs1, idxs2, dists): (Vec<usize>, Vec<usize>, Vec<f64>) =
+        triples.into_iter().multiunzip();
+    (idxs1, idxs2, vec![dists])
+}
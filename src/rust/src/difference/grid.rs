@@ -0,0 +1,132 @@
+// Grid index for difference joins on several columns at once. Right rows are
+// hashed into cells whose width along each column is that column's window
+// reach, so any pair inside every window lies in neighbouring cells on every
+// column. Each left row probes the 3^d cells around its own and checks every
+// column of the candidates, which prunes on all columns together instead of
+// filtering the matches of the first one.
+
+use crate::block::Matches;
+use crate::dedupe::Pairing;
+use crate::difference::Window;
+use rayon::prelude::*;
+use rayon::ThreadPool;
+use rustc_hash::FxHashMap;
+
+// Beyond this many columns the 3^d probed cells outweigh the pruning
+pub const MAX_GRID_DIMS: usize = 4;
+
+type Cell = [i64; MAX_GRID_DIMS];
+
+// Columns with an unbounded window are left out of the cell key, and
+// exact-match windows still need a non-zero width to divide by
+fn cell_widths(windows: &[Window]) -> Vec<Option<f64>> {
+    windows
+        .iter()
+        .map(|window| {
+            let reach = window.reach();
+            if !reach.is_finite() {
+                None
+            } else if reach > 0.0 {
+                Some(reach)
+            } else {
+                Some(1.0)
+            }
+        })
+        .collect()
+}
+
+fn cell(values: &[f64], widths: &[Option<f64>]) -> Cell {
+    let mut cell = [0; MAX_GRID_DIMS];
+    for (c, width) in widths.iter().enumerate() {
+        if let Some(width) = width {
+            cell[c] = (values[c] / width).floor() as i64;
+        }
+    }
+    cell
+}
+
+// Matches with one signed difference `y - x` per column, in the same order as
+// the single-column kernel: by the size of the first difference. `cols1` and
+// `cols2` hold one vector per join column.
+pub fn grid_matches(
+    cols1: &[Vec<f64>],
+    cols2: &[Vec<f64>],
+    windows: &[Window],
+    pairing: Pairing,
+    pool: &ThreadPool,
+) -> Matches {
+    let ncols = windows.len();
+    let widths = cell_widths(windows);
+    let row = |cols: &[Vec<f64>], i: usize| -> Option<Vec<f64>> {
+        let values: Vec<f64> = cols.iter().map(|col| col[i]).collect();
+        values.iter().all(|x| !x.is_nan()).then_some(values)
+    };
+
+    // Rows with a missing value never match
+    let rows2: Vec<Option<Vec<f64>>> = (0..cols2[0].len()).map(|j| row(cols2, j)).collect();
+    let mut cells: FxHashMap<Cell, Vec<usize>> = FxHashMap::default();
+    for (j, values) in rows2.iter().enumerate() {
+        if let Some(values) = values {
+            cells.entry(cell(values, &widths)).or_default().push(j);
+        }
+    }
+
+    // Offsets of the neighbouring cells, -1..=1 on every gridded column
+    let gridded: Vec<usize> = (0..ncols).filter(|&c| widths[c].is_some()).collect();
+    let offsets: Vec<Cell> = (0..3usize.pow(gridded.len() as u32))
+        .map(|mut code| {
+            let mut offset = [0; MAX_GRID_DIMS];
+            for &c in &gridded {
+                offset[c] = (code % 3) as i64 - 1;
+                code /= 3;
+            }
+            offset
+        })
+        .collect();
+
+    let mut pairs: Vec<(usize, usize, Vec<f64>)> = pool.install(|| {
+        (0..cols1[0].len())
+            .into_par_iter()
+            .flat_map_iter(|i| {
+                let mut found = Vec::new();
+                let Some(x) = row(cols1, i) else {
+                    return found;
+                };
+                let center = cell(&x, &widths);
+                for offset in &offsets {
+                    let mut probe = center;
+                    probe.iter_mut().zip(offset).for_each(|(p, o)| *p += o);
+                    let Some(candidates) = cells.get(&probe) else {
+                        continue;
+                    };
+                    for &j in candidates {
+                        // A self-join compares each pair of rows once
+                        if !pairing.compares_rows(i, j) {
+                            continue;
+                        }
+                        let Some(y) = &rows2[j] else { continue };
+                        let diffs: Vec<f64> = y.iter().zip(&x).map(|(y, x)| y - x).collect();
+                        if diffs.iter().zip(windows).all(|(&d, w)| w.contains(d)) {
+                            found.push((i + 1, j + 1, diffs));
+                        }
+                    }
+                }
+                found.sort_unstable_by_key(|&(_, j, _)| j);
+                found
+            })
+            .collect()
+    });
+    pairs.sort_by(|a, b| a.2[0].abs().total_cmp(&b.2[0].abs()));
+
+    let mut idxs1 = Vec::with_capacity(pairs.len());
+    let mut idxs2 = Vec::with_capacity(pairs.len());
+    let mut dists: Vec<Vec<f64>> = vec![Vec::with_capacity(pairs.len()); ncols];
+    for (i, j, diffs) in pairs {
+        idxs1.push(i);
+        idxs2.push(j);
+        for (col, d) in dists.iter_mut().zip(diffs) {
+            col.push(d);
+        }
+    }
+    (idxs1, idxs2, dists)
+}
@@ -1,21 +1,213 @@
-use crate::utils::any_numeric_to_vec64;
+pub mod asof;
+pub mod grid;
+
+use crate::block::{blocked_matches, Matches};
+use crate::composite::CompositeScore;
+use crate::dedupe::Pairing;
+use crate::difference::grid::{grid_matches, MAX_GRID_DIMS};
+use crate::options::Options;
+use crate::string::extract_keys;
+use crate::topk::{keep_best_rows, Selection, TopK};
+use crate::utils::{any_numeric_to_vec64, subset_pairs};
 use anyhow::{anyhow, Result};
 use core::f64;
 use extendr_api::prelude::*;
+use itertools::MultiUnzip;
 use rayon::prelude::*;
 use rayon::ThreadPool;
 use rustc_hash::FxHashMap;
 
+// Accepted range of the signed difference `y - x` between a right value `y`
+// and a left value `x`. The usual symmetric window is `|x - y| <= max_distance`;
+// asymmetric ones allow, say, a right event up to two hours after the left
+// one but at most ten minutes before it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Window {
+    pub lower: f64,
+    pub upper: f64,
+}
+
+impl Window {
+    // Bounds left unset fall back to `max_distance` on that side
+    pub fn new(max_distance: f64, lower: Option<f64>, upper: Option<f64>) -> Result<Self> {
+        let lower = lower.unwrap_or(-max_distance);
+        let upper = upper.unwrap_or(max_distance);
+        if lower.is_nan() || upper.is_nan() {
+            return Err(anyhow!("Window bounds must not be NA"));
+        }
+        if lower > upper {
+            return Err(anyhow!(
+                "`lower` ({}) must not exceed `upper` ({})",
+                lower,
+                upper
+            ));
+        }
+        Ok(Window { lower, upper })
+    }
+
+    // One window per join column. `max_distance` holds one threshold for
+    // every column or one per column; the bounds apply to every column.
+    pub fn per_column(
+        max_distance: &[f64],
+        lower: Option<f64>,
+        upper: Option<f64>,
+        ncols: usize,
+    ) -> Result<Vec<Self>> {
+        match max_distance.len() {
+            1 => Ok(vec![Window::new(max_distance[0], lower, upper)?; ncols]),
+            n if n == ncols => max_distance
+                .iter()
+                .map(|&m| Window::new(m, lower, upper))
+                .collect(),
+            n => Err(anyhow!(
+                "Expected one `max_distance` or one per join column ({}), got {}",
+                ncols,
+                n
+            )),
+        }
+    }
+
+    // Differences involving a missing value are never inside the window
+    pub fn contains(&self, diff: f64) -> bool {
+        !diff.is_nan() && !self.below(diff) && !self.above(diff)
+    }
+
+    fn below(&self, diff: f64) -> bool {
+        diff < self.lower - f64::EPSILON
+    }
+
+    fn above(&self, diff: f64) -> bool {
+        diff > self.upper + f64::EPSILON
+    }
+
+    // Largest absolute difference inside the window
+    fn reach(&self) -> f64 {
+        self.lower.abs().max(self.upper.abs())
+    }
+
+    // Share of the window used on the side of `diff`, for composite scores:
+    // 0 for equal values, 1 at the bound, and NaN (a failed column) outside
+    fn fraction(&self, diff: f64) -> f64 {
+        if diff.is_nan() || !self.contains(diff) {
+            f64::NAN
+        } else if diff == 0.0 {
+            0.0
+        } else if diff < 0.0 {
+            diff / self.lower
+        } else {
+            diff / self.upper
+        }
+    }
+}
+
+// Right values sorted ascending, for walking outwards from a left value
+fn sorted_values(vec2: Vec<f64>) -> Vec<(f64, usize)> {
+    let mut sorted: Vec<(f64, usize)> = vec2
+        .into_iter()
+        .enumerate()
+        .filter(|(_, y)| !y.is_nan())
+        .map(|(j_idx, y)| (y, j_idx))
+        .collect();
+    sorted.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1)));
+    sorted
+}
+
+// The closest right values to `x` within the window, found by stepping
+// outwards from its position in `sorted`. Stops once `k` values are found and
+// the next one is strictly further away, so ties at the k-th distance are
+// all returned and left to `TopK::select`.
+fn nearest_diff(
+    sorted: &[(f64, usize)],
+    i_idx: usize,
+    x: f64,
+    window: Window,
+    top_k: &TopK,
+) -> Vec<(usize, usize, f64)> {
+    let mut out: Vec<(usize, usize, f64)> = Vec::new();
+    let mut signs: FxHashMap<usize, f64> = FxHashMap::default();
+    if x.is_nan() {
+        return out;
+    }
+
+    // Right values inside the window occupy `first..last`
+    let first = sorted.partition_point(|(y, _)| window.below(y - x));
+    let last = sorted.partition_point(|(y, _)| !window.above(y - x));
+    let mid = sorted.partition_point(|(y, _)| *y < x).clamp(first, last);
+    let (mut lo, mut hi) = (mid, mid);
+
+    loop {
+        let below = (lo > first).then(|| x - sorted[lo - 1].0);
+        let above = (hi < last).then(|| sorted[hi].0 - x);
+
+        let (diff, j_idx) = match (below, above) {
+            (Some(b), Some(a)) if b <= a => {
+                lo -= 1;
+                (-b, sorted[lo].1)
+            }
+            (_, Some(a)) => {
+                hi += 1;
+                (a, sorted[hi - 1].1)
+            }
+            (Some(b), None) => {
+                lo -= 1;
+                (-b, sorted[lo].1)
+            }
+            (None, None) => break,
+        };
+
+        if out.len() >= top_k.k && out.last().is_some_and(|&(_, _, d)| diff.abs() > d) {
+            break;
+        }
+        // Ranked on the absolute difference, reported with its sign
+        out.push((i_idx + 1, j_idx + 1, diff.abs()));
+        signs.insert(j_idx + 1, diff);
+    }
+
+    top_k
+        .select(out)
+        .into_iter()
+        .map(|(i, j, _)| (i, j, signs[&j]))
+        .collect()
+}
+
 fn fuzzy_indices_diff(
     vec1: Vec<f64>,
     vec2: Vec<f64>,
-    max_distance: f64,
+    window: Window,
+    select: Selection,
     pool: &ThreadPool,
 ) -> (Vec<usize>, Vec<usize>, Vec<f64>) {
-    let indexed_vec1: Vec<(usize, f64)> = vec1.into_iter().enumerate().collect();
-    let indexed_vec2: Vec<(usize, f64)> = vec2.into_iter().enumerate().collect();
+    if let Some(top_k) = select.top_k {
+        let sorted = sorted_values(vec2);
+        let mut triples: Vec<(usize, usize, f64)> = pool.install(|| {
+            vec1.par_iter()
+                .enumerate()
+                .flat_map_iter(|(i_idx, &x)| nearest_diff(&sorted, i_idx, x, window, top_k))
+                .collect()
+        });
+        triples.sort_by(|a, b| a.2.abs().total_cmp(&b.2.abs()));
+        return triples.into_iter().multiunzip();
+    }
+
+    // Missing values never match, and would otherwise land in bucket 0
+    let indexed_vec1: Vec<(usize, f64)> = vec1
+        .into_iter()
+        .enumerate()
+        .filter(|(_, x)| !x.is_nan())
+        .collect();
+    let indexed_vec2: Vec<(usize, f64)> = vec2
+        .into_iter()
+        .enumerate()
+        .filter(|(_, y)| !y.is_nan())
+        .collect();
 
-    let bucket_width = max_distance;
+    // Any pair inside the window is at most one bucket apart. Exact-match
+    // windows still need a non-zero width to divide by.
+    let bucket_width = if window.reach() > 0.0 {
+        window.reach()
+    } else {
+        1.0
+    };
     let buckets: FxHashMap<i64, Vec<(usize, f64)>> = {
         let mut map: FxHashMap<i64, Vec<(usize, f64)>> = FxHashMap::default();
         for (j_idx, y) in indexed_vec2 {
@@ -25,8 +217,6 @@ fn fuzzy_indices_diff(
         map
     };
 
-    let threshold = max_distance + f64::EPSILON;
-
     pool.install(|| {
         let mut lhs_indices = Vec::new();
         let mut rhs_indices = Vec::new();
@@ -42,8 +232,12 @@ fn fuzzy_indices_diff(
                     .filter_map(|b| buckets.get(&b))
                     .flat_map(move |bucket| {
                         bucket.iter().filter_map(move |&(j_idx, y)| {
-                            let diff = (x - y).abs();
-                            if diff <= threshold {
+                            // A self-join compares each pair of rows once
+                            if !select.pairing.compares_rows(i_idx, j_idx) {
+                                return None;
+                            }
+                            let diff = y - x;
+                            if window.contains(diff) {
                                 Some((i_idx + 1, j_idx + 1, diff))
                             } else {
                                 None
@@ -53,7 +247,7 @@ fn fuzzy_indices_diff(
             })
             .collect::<Vec<_>>();
 
-        triples.sort_by(|a, b| a.2.partial_cmp(&b.2).unwrap_or(std::cmp::Ordering::Equal));
+        triples.sort_by(|a, b| a.2.abs().total_cmp(&b.2.abs()));
         triples.into_iter().for_each(|(i, j, d)| {
             lhs_indices.push(i);
             rhs_indices.push(j);
@@ -68,19 +262,180 @@ pub fn difference_join(
     df1: &List,
     df2: &List,
     by: (String, String),
-    max_distance: f64,
+    window: Window,
+    select: Selection,
     pool: &ThreadPool,
 ) -> Result<(Vec<usize>, Vec<usize>, Vec<f64>)> {
     let lk = by.0.as_str();
     let rk = by.1.as_str();
 
-    let vec1 = any_numeric_to_vec64(df1, lk)?;
-    let vec2 = any_numeric_to_vec64(df2, rk)?;
+    let vec1 = any_numeric_to_vec64(df1, lk, "df1")?;
+    let vec2 = any_numeric_to_vec64(df2, rk, "df2")?;
 
-    let (idxs1, idxs2, dists) = fuzzy_indices_diff(vec1, vec2, max_distance, &pool);
+    let (idxs1, idxs2, dists) = fuzzy_indices_diff(vec1, vec2, window, select, pool);
     Ok((idxs1, idxs2, dists))
 }
 
+// What a difference join matches on: a window per key column, plus the
+// composite score and best-match selection when they are set. Key distances
+// are the signed differences `y - x` when `signed` is set and absolute ones
+// otherwise; ranking always uses the absolute difference.
+pub struct DifferenceSpec {
+    keys: Vec<(String, String)>,
+    windows: Vec<Window>,
+    composite: Option<CompositeScore>,
+    top_k: Option<TopK>,
+    signed: bool,
+}
+
+impl DifferenceSpec {
+    pub fn new(options: &Options, by: &List, top_k: Option<TopK>) -> Result<Self> {
+        let keys = extract_keys(by)?;
+        let max_distance: Vec<f64> = options
+            .get("max_distance")?
+            .ok_or_else(|| anyhow!("Must provide `max_distance`"))?;
+        let windows = Window::per_column(
+            &max_distance,
+            options.get("lower")?,
+            options.get("upper")?,
+            keys.len(),
+        )?;
+        let reaches: Vec<f64> = windows.iter().map(Window::reach).collect();
+        let composite =
+            CompositeScore::new(options.get("weights")?, options.get("max_score")?, &reaches)?;
+
+        // Otherwise best matches are picked only after the first column has
+        // compared every pair of rows
+        let single = keys.len() == 1 && composite.is_none();
+        if top_k.is_some() && !single && !windows[0].reach().is_finite() {
+            return Err(anyhow!(
+                "With several `by` columns or `weights`, `k` needs a finite `max_distance` for the first column"
+            ));
+        }
+
+        Ok(DifferenceSpec {
+            keys,
+            windows,
+            composite,
+            top_k,
+            signed: options.flag("signed")?,
+        })
+    }
+
+    // Matched pairs with one distance vector per key column, plus the
+    // composite score last when there is one. In a self-join, `df1` and
+    // `df2` are the same table and only pairs with i < j are found.
+    pub fn pairs(
+        &self,
+        df1: &List,
+        df2: &List,
+        block_by: Option<&List>,
+        pairing: Pairing,
+        pool: &ThreadPool,
+    ) -> Result<Matches> {
+        let cols1: Vec<&str> = self.keys.iter().map(|(lk, _)| lk.as_str()).collect();
+        let cols2: Vec<&str> = self.keys.iter().map(|(_, rk)| rk.as_str()).collect();
+        let ndists = self.keys.len() + usize::from(self.composite.is_some());
+        blocked_matches(df1, df2, block_by, &cols1, &cols2, ndists, |df1, df2| {
+            self.matches(df1, df2, pairing, pool)
+        })
+    }
+
+    fn matches(
+        &self,
+        df1: &List,
+        df2: &List,
+        pairing: Pairing,
+        pool: &ThreadPool,
+    ) -> Result<Matches> {
+        let (keys, windows) = (&self.keys, &self.windows);
+        let composite = self.composite.as_ref();
+        let top_k = self.top_k.as_ref();
+
+        // Best matches on several columns or by composite score are chosen
+        // after every pair is scored
+        let single = keys.len() == 1 && composite.is_none();
+        let select = Selection {
+            top_k: if single { top_k } else { None },
+            pairing,
+        };
+
+        // Pairs must fall inside every window unless they are scored, so a
+        // few columns can be indexed together
+        let grid = composite.is_none() && (2..=MAX_GRID_DIMS).contains(&keys.len());
+
+        let (mut idxs1, mut idxs2, mut dists) = if grid {
+            let cols1 = keys
+                .iter()
+                .map(|(lk, _)| any_numeric_to_vec64(df1, lk, "df1"))
+                .collect::<Result<Vec<_>>>()?;
+            let cols2 = keys
+                .iter()
+                .map(|(_, rk)| any_numeric_to_vec64(df2, rk, "df2"))
+                .collect::<Result<Vec<_>>>()?;
+            grid_matches(&cols1, &cols2, windows, pairing, pool)
+        } else {
+            let (idxs1, idxs2, dists) =
+                difference_join(df1, df2, keys[0].clone(), windows[0], select, pool)
+                    .map_err(|e| anyhow!("Failed initial difference join: {}", e))?;
+            (idxs1, idxs2, vec![dists])
+        };
+
+        if single {
+            // Best matches were already chosen by the kernel
+        } else if let Some(composite) = composite {
+            // Score every candidate from the first column on all columns, then
+            // filter on the weighted score rather than on each column
+            for bypair in &keys[1..] {
+                dists.push(
+                    difference_pair_distances(df1, &idxs1, df2, &idxs2, bypair, pool)
+                        .map_err(|e| anyhow!("Failed distances for {:?}: {}", bypair, e))?,
+                );
+            }
+
+            // Each column is scaled by the window bound on its side
+            let fractions: Vec<Vec<f64>> = dists
+                .iter()
+                .zip(windows)
+                .map(|(col, window)| col.iter().map(|&d| window.fraction(d)).collect())
+                .collect();
+            dists.push(composite.scores(&fractions, &vec![1.0; keys.len()]));
+            let keep = composite.passing(&dists[keys.len()]);
+            (idxs1, idxs2, dists) = subset_pairs(&idxs1, &idxs2, &dists, &keep);
+
+            // Rank the surviving pairs by their composite score
+            if let Some(top_k) = top_k {
+                let keep = keep_best_rows(&idxs1, &idxs2, &dists[keys.len()], top_k);
+                (idxs1, idxs2, dists) = subset_pairs(&idxs1, &idxs2, &dists, &keep);
+            }
+        } else {
+            if !grid {
+                for (bypair, &window) in keys[1..].iter().zip(&windows[1..]) {
+                    (idxs1, idxs2, dists) =
+                        difference_pairs(df1, &idxs1, df2, &idxs2, bypair, &dists, window, pool)
+                            .map_err(|e| {
+                                anyhow!("Failed difference_pairs for {:?}: {}", bypair, e)
+                            })?;
+                }
+            }
+
+            // Rank the surviving pairs by the distance on the first column
+            if let Some(top_k) = top_k {
+                let first: Vec<f64> = dists[0].iter().map(|d| d.abs()).collect();
+                let keep = keep_best_rows(&idxs1, &idxs2, &first, top_k);
+                (idxs1, idxs2, dists) = subset_pairs(&idxs1, &idxs2, &dists, &keep);
+            }
+        }
+
+        if !self.signed {
+            for col in dists.iter_mut().take(keys.len()) {
+                col.iter_mut().for_each(|d| *d = d.abs());
+            }
+        }
+        Ok((idxs1, idxs2, dists))
+    }
+}
+
 pub fn difference_pairs(
     df1: &List,
     idxs1: &Vec<usize>,
@@ -88,58 +443,27 @@ pub fn difference_pairs(
     idxs2: &Vec<usize>,
     by: &(String, String),
     dists: &Vec<Vec<f64>>,
-    max_distance: f64,
+    window: Window,
     pool: &ThreadPool,
 ) -> Result<(Vec<usize>, Vec<usize>, Vec<Vec<f64>>)> {
     let lk = by.0.as_str();
     let rk = by.1.as_str();
 
-    let vec1_binding = df1
-        .dollar(lk)
-        .map_err(|_| anyhow!("Missing column '{}' in df1", lk))?
-        .slice(idxs1)
-        .map_err(|_| anyhow!("Failed to slice df1 column '{}'", lk))?;
-
-    let vec1: Vec<f64> = if let Some(v) = vec1_binding.as_real_vector() {
-        v.to_vec()
-    } else if let Some(v) = vec1_binding.as_integer_vector() {
-        v.iter().map(|&x| x as f64).collect()
-    } else {
-        return Err(anyhow!(
-            "Column `{}` in df1 is not numeric (integer or double)",
-            lk
-        ));
-    };
-
-    let vec2_binding = df2
-        .dollar(rk)
-        .map_err(|_| anyhow!("Missing column '{}' in df1", rk))?
-        .slice(idxs2)
-        .map_err(|_| anyhow!("Failed to slice df1 column '{}'", rk))?;
-
-    let vec2: Vec<f64> = if let Some(v) = vec2_binding.as_real_vector() {
-        v.to_vec()
-    } else if let Some(v) = vec2_binding.as_integer_vector() {
-        v.iter().map(|&x| x as f64).collect()
-    } else {
-        return Err(anyhow!(
-            "Column `{}` in df1 is not numeric (integer or double)",
-            lk
-        ));
-    };
-
-    let threshold = max_distance + f64::EPSILON;
+    let vec1 = any_numeric_to_vec64(df1, lk, "df1")?;
+    let vec2 = any_numeric_to_vec64(df2, rk, "df2")?;
 
     let (idxs0, newdist): (Vec<usize>, Vec<f64>) = pool.install(|| {
-        vec1.par_iter()
-            .zip(vec2)
+        idxs1
+            .par_iter()
+            .zip(idxs2)
             .enumerate()
-            .filter_map(|(i, (left, right))| {
-                if left.is_na() || right.is_na() {
+            .filter_map(|(i, (&l, &r))| {
+                let (left, right) = (vec1[l - 1], vec2[r - 1]);
+                if left.is_nan() || right.is_nan() {
                     return None;
                 }
-                let diff = (left - right).abs();
-                if diff <= threshold {
+                let diff = right - left;
+                if window.contains(diff) {
                     Some((i, diff))
                 } else {
                     None
@@ -158,3 +482,37 @@ pub fn difference_pairs(
     dists_out.push(newdist);
     Ok((idxs1b, idxs2b, dists_out))
 }
+
+// Signed difference `y - x` on one more column for every candidate pair,
+// with `NA` where either value is missing
+pub fn difference_pair_distances(
+    df1: &List,
+    idxs1: &Vec<usize>,
+    df2: &List,
+    idxs2: &Vec<usize>,
+    by: &(String, String),
+    pool: &ThreadPool,
+) -> Result<Vec<f64>> {
+    let lk = by.0.as_str();
+    let rk = by.1.as_str();
+
+    let vec1 = any_numeric_to_vec64(df1, lk, "df1")?;
+    let vec2 = any_numeric_to_vec64(df2, rk, "df2")?;
+
+    let na = f64::na();
+    let out = pool.install(|| {
+        idxs1
+            .par_iter()
+            .zip(idxs2)
+            .map(|(i, j)| {
+                let (left, right) = (vec1[i - 1], vec2[j - 1]);
+                if left.is_nan() || right.is_nan() {
+                    na
+                } else {
+                    right - left
+                }
+            })
+            .collect()
+    });
+    Ok(out)
+}
@@ -0,0 +1,187 @@
+// Geodesic distances between (latitude, longitude) rows given in degrees,
+// returned in metres. Haversine treats the earth as a sphere of mean radius;
+// Vincenty solves the inverse problem on the WGS84 ellipsoid.
+//
+// Degrees are not Cartesian coordinates, so the spatial index works on unit
+// vectors instead: the straight-line (chord) distance between two points on
+// the unit sphere grows with the angle between them, so a radius query in
+// metres becomes a chord query against a KD-tree of unit vectors. Distances
+// on the ellipsoid stay within a fraction of a percent of the spherical ones,
+// and `SLACK` widens every chord radius enough to cover that. Candidates are
+// then checked against the exact distance.
+
+use crate::distance::kdtree::KdTree;
+use crate::distance::DistanceMetric;
+use crate::topk::{keep_best, Selection};
+use anyhow::{anyhow, Result};
+use itertools::MultiUnzip;
+use rayon::prelude::*;
+use rayon::ThreadPool;
+use std::f64::consts::PI;
+
+// Mean earth radius (IUGG), in metres
+pub const EARTH_RADIUS: f64 = 6_371_008.8;
+
+// WGS84 semi-major axis and flattening
+const WGS84_A: f64 = 6_378_137.0;
+const WGS84_F: f64 = 1.0 / 298.257_223_563;
+
+// Relative margin between spherical and ellipsoidal distances
+const SLACK: f64 = 0.01;
+
+const VINCENTY_ITERATIONS: usize = 200;
+
+// Rows must hold a latitude in [-90, 90]; missing values never match
+pub fn check_coordinates(rows: &[Vec<f64>], side: &str) -> Result<()> {
+    match rows
+        .iter()
+        .position(|row| row[0].abs() > 90.0 || row[1].is_infinite())
+    {
+        Some(i) => Err(anyhow!(
+            "Row {} of {} is not a valid latitude/longitude pair",
+            i + 1,
+            side
+        )),
+        None => Ok(()),
+    }
+}
+
+pub fn haversine(a: &[f64], b: &[f64]) -> f64 {
+    let (lat1, lat2) = (a[0].to_radians(), b[0].to_radians());
+    let dlat = lat2 - lat1;
+    let dlon = (b[1] - a[1]).to_radians();
+    let h = (dlat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (dlon / 2.0).sin().powi(2);
+    // `clamp` keeps a missing coordinate missing
+    2.0 * EARTH_RADIUS * h.sqrt().clamp(0.0, 1.0).asin()
+}
+
+// Vincenty's inverse formula. Nearly antipodal points can fail to converge,
+// in which case the haversine distance is returned instead.
+pub fn vincenty(a: &[f64], b: &[f64]) -> f64 {
+    if a.iter().chain(b).any(|x| x.is_nan()) {
+        return f64::NAN;
+    }
+    let semi_minor = (1.0 - WGS84_F) * WGS84_A;
+    let l = (b[1] - a[1]).to_radians();
+    let u1 = ((1.0 - WGS84_F) * a[0].to_radians().tan()).atan();
+    let u2 = ((1.0 - WGS84_F) * b[0].to_radians().tan()).atan();
+    let (sin_u1, cos_u1) = u1.sin_cos();
+    let (sin_u2, cos_u2) = u2.sin_cos();
+
+    let mut lambda = l;
+    for _ in 0..VINCENTY_ITERATIONS {
+        let (sin_lambda, cos_lambda) = lambda.sin_cos();
+        let sin_sigma = ((cos_u2 * sin_lambda).powi(2)
+            + (cos_u1 * sin_u2 - sin_u1 * cos_u2 * cos_lambda).powi(2))
+        .sqrt();
+        if sin_sigma == 0.0 {
+            return 0.0;
+        }
+        let cos_sigma = sin_u1 * sin_u2 + cos_u1 * cos_u2 * cos_lambda;
+        let sigma = sin_sigma.atan2(cos_sigma);
+        let sin_alpha = cos_u1 * cos_u2 * sin_lambda / sin_sigma;
+        let cos2_alpha = 1.0 - sin_alpha * sin_alpha;
+        // Zero on the equator
+        let cos_2sigma_m = if cos2_alpha == 0.0 {
+            0.0
+        } else {
+            cos_sigma - 2.0 * sin_u1 * sin_u2 / cos2_alpha
+        };
+        let c = WGS84_F / 16.0 * cos2_alpha * (4.0 + WGS84_F * (4.0 - 3.0 * cos2_alpha));
+        let previous = lambda;
+        lambda = l
+            + (1.0 - c)
+                * WGS84_F
+                * sin_alpha
+                * (sigma
+                    + c * sin_sigma
+                        * (cos_2sigma_m
+                            + c * cos_sigma * (-1.0 + 2.0 * cos_2sigma_m * cos_2sigma_m)));
+
+        if (lambda - previous).abs() < 1e-12 {
+            let u_sq = cos2_alpha * (WGS84_A.powi(2) - semi_minor.powi(2)) / semi_minor.powi(2);
+            let big_a =
+                1.0 + u_sq / 16384.0 * (4096.0 + u_sq * (-768.0 + u_sq * (320.0 - 175.0 * u_sq)));
+            let big_b = u_sq / 1024.0 * (256.0 + u_sq * (-128.0 + u_sq * (74.0 - 47.0 * u_sq)));
+            let delta_sigma = big_b
+                * sin_sigma
+                * (cos_2sigma_m
+                    + big_b / 4.0
+                        * (cos_sigma * (-1.0 + 2.0 * cos_2sigma_m * cos_2sigma_m)
+                            - big_b / 6.0
+                                * cos_2sigma_m
+                                * (-3.0 + 4.0 * sin_sigma * sin_sigma)
+                                * (-3.0 + 4.0 * cos_2sigma_m * cos_2sigma_m)));
+            return semi_minor * big_a * (sigma - delta_sigma);
+        }
+    }
+    haversine(a, b)
+}
+
+fn unit_vector(row: &[f64]) -> Vec<f64> {
+    let (sin_lat, cos_lat) = row[0].to_radians().sin_cos();
+    let (sin_lon, cos_lon) = row[1].to_radians().sin_cos();
+    vec![cos_lat * cos_lon, cos_lat * sin_lon, sin_lat]
+}
+
+// Chord radius that covers every point within `metres`
+fn chord_radius(metres: f64) -> f64 {
+    let angle = metres / EARTH_RADIUS * (1.0 + SLACK);
+    if angle >= PI {
+        2.0 + SLACK
+    } else {
+        2.0 * (angle / 2.0).sin()
+    }
+}
+
+// Upper bound, in metres, on the distance between two points `chord` apart
+fn metres_bound(chord: f64) -> f64 {
+    2.0 * (chord / 2.0).min(1.0).asin() * EARTH_RADIUS * (1.0 + SLACK)
+}
+
+// Radius join on geodesic distance through a KD-tree of unit vectors. Best
+// matches take the k-th nearest unit vector as an upper bound on the k-th
+// nearest distance, and `keep_best` settles the exact order.
+pub fn geodesic_distances(
+    left: &[Vec<f64>],
+    right: &[Vec<f64>],
+    threshold: f64,
+    metric: DistanceMetric,
+    select: Selection,
+    pool: &ThreadPool,
+) -> Result<(Vec<usize>, Vec<usize>, Vec<f64>)> {
+    let points1: Vec<Vec<f64>> = left.iter().map(|row| unit_vector(row)).collect();
+    let points2: Vec<Vec<f64>> = right.iter().map(|row| unit_vector(row)).collect();
+    let tree = KdTree::new(&points2, DistanceMetric::Euclidean);
+
+    pool.install(|| {
+        let triples: Vec<Vec<(usize, usize, f64)>> = points1
+            .par_iter()
+            .enumerate()
+            .map(|(i, point)| {
+                let radius = match select
+                    .top_k
+                    .and_then(|top_k| tree.kth_distance(point, top_k.k))
+                {
+                    Some(kth) => metres_bound(kth).min(threshold),
+                    None => threshold,
+                };
+                let mut row: Vec<(usize, usize, f64)> = tree
+                    .within(point, chord_radius(radius))
+                    .into_iter()
+                    .filter(|&(j, _)| select.pairing.compares_rows(i, j))
+                    .filter_map(|(j, _)| {
+                        let dist = metric.distance(&left[i], &right[j]);
+                        (dist <= radius).then_some((i + 1, j + 1, dist))
+                    })
+                    .collect();
+                row.sort_unstable_by_key(|&(_, j, _)| j);
+                keep_best(row, select.top_k)
+            })
+            .collect();
+
+        let (left_indices, right_indices, distances): (Vec<_>, Vec<_>, Vec<_>) =
+            triples.into_iter().flatten().multiunzip();
+        Ok((left_indices, right_indices, distances))
+    })
+}
@@ -0,0 +1,181 @@
+// KD-tree over the right-hand rows of a distance join. Points are split on
+// the median of their widest coordinate until a leaf holds at most
+// `LEAF_SIZE` of them, and queries descend the near side of every split
+// first. A split's far side is skipped once the gap along the split axis
+// alone exceeds the search radius, which is a valid lower bound for every
+// metric whose `axis_bound` is not larger than the full distance.
+
+use crate::distance::DistanceMetric;
+
+const LEAF_SIZE: usize = 16;
+
+enum Node {
+    Leaf {
+        start: usize,
+        end: usize,
+    },
+    Split {
+        axis: usize,
+        value: f64,
+        left: usize,
+        right: usize,
+    },
+}
+
+pub struct KdTree<'a> {
+    points: &'a [Vec<f64>],
+    // Point indices, reordered so that each leaf owns a contiguous range
+    order: Vec<usize>,
+    nodes: Vec<Node>,
+    metric: DistanceMetric,
+}
+
+impl<'a> KdTree<'a> {
+    // Points with a missing coordinate can never be within any distance, so
+    // they are left out of the tree
+    pub fn new(points: &'a [Vec<f64>], metric: DistanceMetric) -> Self {
+        let order: Vec<usize> = (0..points.len())
+            .filter(|&j| points[j].iter().all(|x| !x.is_nan()))
+            .collect();
+        let mut tree = KdTree {
+            points,
+            order,
+            nodes: Vec::new(),
+            metric,
+        };
+        let n = tree.order.len();
+        tree.build(0, n);
+        tree
+    }
+
+    fn build(&mut self, start: usize, end: usize) -> usize {
+        let id = self.nodes.len();
+        let axis = self.widest_axis(start, end);
+        let Some(axis) = axis.filter(|_| end - start > LEAF_SIZE) else {
+            self.nodes.push(Node::Leaf { start, end });
+            return id;
+        };
+
+        let mid = start + (end - start) / 2;
+        let points = self.points;
+        self.order[start..end].select_nth_unstable_by(mid - start, |&a, &b| {
+            points[a][axis].total_cmp(&points[b][axis])
+        });
+        let value = points[self.order[mid]][axis];
+
+        // Reserve the slot, then fill in the children
+        self.nodes.push(Node::Leaf { start, end });
+        let left = self.build(start, mid);
+        let right = self.build(mid, end);
+        self.nodes[id] = Node::Split {
+            axis,
+            value,
+            left,
+            right,
+        };
+        id
+    }
+
+    // Coordinate with the largest spread in `order[start..end]`, or `None`
+    // when every point is identical
+    fn widest_axis(&self, start: usize, end: usize) -> Option<usize> {
+        let dims = self.order.get(start).map_or(0, |&j| self.points[j].len());
+        (0..dims)
+            .map(|axis| {
+                let (lo, hi) = self.order[start..end].iter().fold(
+                    (f64::INFINITY, f64::NEG_INFINITY),
+                    |(lo, hi), &j| {
+                        let x = self.points[j][axis];
+                        (lo.min(x), hi.max(x))
+                    },
+                );
+                (axis, hi - lo)
+            })
+            .filter(|&(_, spread)| spread > 0.0)
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(axis, _)| axis)
+    }
+
+    // Every point within `radius` of `query`, as (index, distance) pairs
+    pub fn within(&self, query: &[f64], radius: f64) -> Vec<(usize, f64)> {
+        let mut out = Vec::new();
+        if !self.order.is_empty() && !query.iter().any(|x| x.is_nan()) {
+            self.search(0, query, radius, &mut out);
+        }
+        out
+    }
+
+    fn search(&self, id: usize, query: &[f64], radius: f64, out: &mut Vec<(usize, f64)>) {
+        match self.nodes[id] {
+            Node::Leaf { start, end } => {
+                for &j in &self.order[start..end] {
+                    let dist = self.metric.distance(query, &self.points[j]);
+                    if dist <= radius {
+                        out.push((j, dist));
+                    }
+                }
+            }
+            Node::Split {
+                axis,
+                value,
+                left,
+                right,
+            } => {
+                let gap = query[axis] - value;
+                let (near, far) = if gap < 0.0 {
+                    (left, right)
+                } else {
+                    (right, left)
+                };
+                self.search(near, query, radius, out);
+                if self.metric.axis_bound(gap) <= radius {
+                    self.search(far, query, radius, out);
+                }
+            }
+        }
+    }
+
+    // Distance from `query` to its `k`-th nearest point, or `None` when the
+    // tree holds fewer than `k` points
+    pub fn kth_distance(&self, query: &[f64], k: usize) -> Option<f64> {
+        if k == 0 || self.order.len() < k || query.iter().any(|x| x.is_nan()) {
+            return None;
+        }
+        // The `k` smallest distances seen so far, ascending
+        let mut best: Vec<f64> = Vec::with_capacity(k + 1);
+        self.nearest(0, query, k, &mut best);
+        best.get(k - 1).copied()
+    }
+
+    fn nearest(&self, id: usize, query: &[f64], k: usize, best: &mut Vec<f64>) {
+        match self.nodes[id] {
+            Node::Leaf { start, end } => {
+                for &j in &self.order[start..end] {
+                    let dist = self.metric.distance(query, &self.points[j]);
+                    if best.len() < k || dist < best[k - 1] {
+                        let pos = best.partition_point(|&d| d <= dist);
+                        best.insert(pos, dist);
+                        best.truncate(k);
+                    }
+                }
+            }
+            Node::Split {
+                axis,
+                value,
+                left,
+                right,
+            } => {
+                let gap = query[axis] - value;
+                let (near, far) = if gap < 0.0 {
+                    (left, right)
+                } else {
+                    (right, left)
+                };
+                self.nearest(near, query, k, best);
+                if best.len() < k || self.metric.axis_bound(gap) <= best[k - 1] {
+                    self.nearest(far, query, k, best);
+                }
+            }
+        }
+    }
+}
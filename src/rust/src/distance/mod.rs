@@ -1,3 +1,10 @@
+pub mod geo;
+pub mod kdtree;
+
+use crate::distance::kdtree::KdTree;
+use crate::options::Options;
+use crate::topk::{keep_best, Selection};
+use crate::utils::any_numeric_to_vec64;
 use anyhow::{anyhow, Result};
 use core::f64;
 use extendr_api::prelude::*;
@@ -20,12 +27,22 @@ fn zip_columns(columns: &[Vec<f64>]) -> Vec<Vec<f64>> {
         .collect::<Vec<_>>()
 }
 
+// Rows of the given numeric columns of `df`. Missing values are NaN, and a
+// row with one in any column never matches.
+fn numeric_rows(df: &List, cols: &[&str], side: &str) -> Result<Vec<Vec<f64>>> {
+    let columns = cols
+        .iter()
+        .map(|col| any_numeric_to_vec64(df, col, side))
+        .collect::<Result<Vec<Vec<f64>>>>()?;
+    Ok(zip_columns(&columns))
+}
+
 pub fn fuzzy_indices_dist(
     df1: &List,
     df2: &List,
     by: &List,
-    method: &str,
-    max_distance: f64,
+    spec: &DistanceSpec,
+    select: Selection,
     pool: &ThreadPool,
 ) -> Result<(Vec<usize>, Vec<usize>, Vec<f64>)> {
     let keys: Vec<(String, String)> = by
@@ -38,100 +55,503 @@ pub fn fuzzy_indices_dist(
         })
         .collect::<Result<_>>()?;
 
-    let distmetric = DistanceMetric::new(method)?;
+    let cols1: Vec<&str> = keys.iter().map(|(lk, _)| lk.as_str()).collect();
+    let cols2: Vec<&str> = keys.iter().map(|(_, rk)| rk.as_str()).collect();
+    let mut left_rows = numeric_rows(df1, &cols1, "df1")?;
+    let mut right_rows = numeric_rows(df2, &cols2, "df2")?;
 
-    let mut left_vecs = Vec::new();
-    let mut right_vecs = Vec::new();
-
-    for (left_key, right_key) in &keys {
-        let leftvec = df1
-            .dollar(left_key)
-            .map_err(|_| anyhow!("Column '{}' not found in df1", left_key))?
-            .as_real_vector()
-            .ok_or_else(|| anyhow!("Column '{}' in df1 is not numeric", left_key))?;
-        left_vecs.push(leftvec);
-
-        let rightvec = df2
-            .dollar(right_key)
-            .map_err(|_| anyhow!("Column '{}' not found in df2", right_key))?
-            .as_real_vector()
-            .ok_or_else(|| anyhow!("Column '{}' in df2 is not numeric", right_key))?;
-        right_vecs.push(rightvec);
+    let metric = spec.metric;
+    if metric.geodesic() {
+        if keys.len() != 2 {
+            return Err(anyhow!(
+                "Geodesic distances need exactly two join columns: latitude, then longitude"
+            ));
+        }
+        geo::check_coordinates(&left_rows, "df1")?;
+        geo::check_coordinates(&right_rows, "df2")?;
     }
 
-    let left_rows = zip_columns(&left_vecs);
-    let right_rows = zip_columns(&right_vecs);
+    spec.transform(&mut left_rows);
+    spec.transform(&mut right_rows);
 
-    let (idxs1, idxs2, dists) =
-        filtered_distances(&left_rows, &right_rows, max_distance, distmetric, pool)?;
+    let (idxs1, idxs2, dists) = filtered_distances(
+        &left_rows,
+        &right_rows,
+        spec.max_distance,
+        metric,
+        select,
+        pool,
+    )?;
 
     Ok((idxs1, idxs2, dists))
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum DistanceMetric {
     Euclidean,
     Manhattan,
+    Chebyshev,
+    Minkowski(f64),
+    Cosine,
+    // Euclidean distance between rows whitened by `Whitening`
+    Mahalanobis,
+    // Metres between (latitude, longitude) rows; see `geo`
+    Haversine,
+    Vincenty,
 }
 
 impl DistanceMetric {
-    pub fn new(s: &str) -> Result<Self> {
+    // `p` is the exponent of the Minkowski distance and ignored otherwise
+    pub fn new(s: &str, p: Option<f64>) -> Result<Self> {
         match s.to_lowercase().as_str() {
             "euclidean" | "euc" => Ok(DistanceMetric::Euclidean),
             "manhattan" | "man" => Ok(DistanceMetric::Manhattan),
+            "chebyshev" | "cheb" => Ok(DistanceMetric::Chebyshev),
+            "minkowski" | "mink" => {
+                let p = p.ok_or_else(|| anyhow!("Minkowski distance needs an exponent `p`"))?;
+                if p.is_nan() || p <= 0.0 {
+                    return Err(anyhow!("Minkowski exponent `p` must be positive"));
+                }
+                Ok(match p {
+                    1.0 => DistanceMetric::Manhattan,
+                    2.0 => DistanceMetric::Euclidean,
+                    p if p.is_infinite() => DistanceMetric::Chebyshev,
+                    p => DistanceMetric::Minkowski(p),
+                })
+            }
+            "cosine" | "cos" => Ok(DistanceMetric::Cosine),
+            "mahalanobis" | "mahal" => Ok(DistanceMetric::Mahalanobis),
+            "haversine" | "hav" => Ok(DistanceMetric::Haversine),
+            "vincenty" => Ok(DistanceMetric::Vincenty),
             _ => Err(anyhow!("Unknown distance metric: {}", s)),
         }
     }
+
+    pub fn distance(&self, a: &[f64], b: &[f64]) -> f64 {
+        let diffs = a.iter().zip(b.iter()).map(|(x, y)| (x - y).abs());
+        match self {
+            DistanceMetric::Euclidean | DistanceMetric::Mahalanobis => {
+                diffs.map(|d| d * d).sum::<f64>().sqrt()
+            }
+            DistanceMetric::Manhattan => diffs.sum::<f64>(),
+            // `f64::max` would drop a missing coordinate
+            DistanceMetric::Chebyshev => {
+                diffs.fold(0.0, |acc, d| if d.is_nan() || d > acc { d } else { acc })
+            }
+            DistanceMetric::Minkowski(p) => diffs.map(|d| d.powf(*p)).sum::<f64>().powf(1.0 / p),
+            // One minus the cosine similarity; undefined for zero vectors
+            DistanceMetric::Cosine => {
+                let (dot, na, nb) = a
+                    .iter()
+                    .zip(b.iter())
+                    .fold((0.0, 0.0, 0.0), |(dot, na, nb), (x, y)| {
+                        (dot + x * y, na + x * x, nb + y * y)
+                    });
+                if na == 0.0 || nb == 0.0 {
+                    f64::NAN
+                } else {
+                    (1.0 - dot / (na * nb).sqrt()).max(0.0)
+                }
+            }
+            DistanceMetric::Haversine => geo::haversine(a, b),
+            DistanceMetric::Vincenty => geo::vincenty(a, b),
+        }
+    }
+
+    // Lower bound on the distance between two points whose coordinates
+    // differ by `gap` along one axis, used to prune the spatial index. Every
+    // Minkowski-type distance is at least its largest coordinate gap; the
+    // bound is rounded the same way as `distance` so pruning stays exact.
+    pub fn axis_bound(&self, gap: f64) -> f64 {
+        match self {
+            DistanceMetric::Minkowski(p) => gap.abs().powf(*p).powf(1.0 / p),
+            _ => gap.abs(),
+        }
+    }
+
+    // Cosine distance depends on direction rather than position, so it
+    // cannot be pruned by coordinate gaps. Geodesic distances are not
+    // measured on the raw coordinates and have an index of their own.
+    pub fn indexable(&self) -> bool {
+        !matches!(
+            self,
+            DistanceMetric::Cosine | DistanceMetric::Haversine | DistanceMetric::Vincenty
+        )
+    }
+
+    pub fn geodesic(&self) -> bool {
+        matches!(self, DistanceMetric::Haversine | DistanceMetric::Vincenty)
+    }
+}
+
+// How rows are compared: the metric, its threshold, and the linear map
+// applied to both sides' rows beforehand. Per-column weights and
+// standardisation scale each column; a box join scales each column by its
+// own threshold and keeps rows whose largest scaled difference is at most 1;
+// Mahalanobis distances whiten the rows.
+pub struct DistanceSpec {
+    pub metric: DistanceMetric,
+    pub max_distance: f64,
+    scaling: Option<Vec<f64>>,
+    whitening: Option<Whitening>,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Standardize {
+    ZScore,
+    MinMax,
+}
+
+impl Standardize {
+    pub fn new(method: Option<&str>) -> Result<Option<Self>> {
+        match method {
+            None => Ok(None),
+            Some("zscore") => Ok(Some(Standardize::ZScore)),
+            Some("minmax") => Ok(Some(Standardize::MinMax)),
+            Some(other) => Err(anyhow!(
+                "Unknown standardisation `{}`; use \"zscore\" or \"minmax\"",
+                other
+            )),
+        }
+    }
+
+    // Sample standard deviation or range of the values
+    fn spread(&self, values: &[f64]) -> f64 {
+        let n = values.len() as f64;
+        match self {
+            Standardize::ZScore => {
+                let mean = values.iter().sum::<f64>() / n;
+                (values.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / (n - 1.0)).sqrt()
+            }
+            Standardize::MinMax => {
+                let (lo, hi) = values
+                    .iter()
+                    .fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), &x| {
+                        (lo.min(x), hi.max(x))
+                    });
+                hi - lo
+            }
+        }
+    }
+}
+
+impl DistanceSpec {
+    // Reads `method`, `p`, `cov`, `weights`, `standardize` and
+    // `max_distance` from `options`. `max_distance` holds a single
+    // threshold, or one per join column for a box join, which ignores
+    // `method`. Standardisation and an estimated covariance matrix use the
+    // rows of `df1` and `df2` pooled together; self-joins pass no `df2` so
+    // that rows are not counted twice.
+    pub fn new(
+        options: &Options,
+        df1: &List,
+        df2: Option<&List>,
+        keys: &[(String, String)],
+    ) -> Result<Self> {
+        let method: String = options
+            .get("method")?
+            .ok_or_else(|| anyhow!("Must provide `method`"))?;
+        let cov: Option<Vec<f64>> = options.get("cov")?;
+        let weights: Option<Vec<f64>> = options.get("weights")?;
+        let max_distance: Vec<f64> = options
+            .get("max_distance")?
+            .ok_or_else(|| anyhow!("Must provide `max_distance`"))?;
+
+        let dims = keys.len();
+        let metric = DistanceMetric::new(&method, options.get("p")?)?;
+        let standardize = Standardize::new(options.get::<String>("standardize")?.as_deref())?;
+        let scaled = weights.is_some() || standardize.is_some();
+
+        if let Some(weights) = &weights {
+            if weights.len() != dims {
+                return Err(anyhow!(
+                    "Expected {} weights (one per join column), got {}",
+                    dims,
+                    weights.len()
+                ));
+            }
+            if weights.iter().any(|w| !w.is_finite() || *w < 0.0) {
+                return Err(anyhow!("`weights` must be finite and non-negative"));
+            }
+        }
+
+        if max_distance.len() > 1 {
+            if max_distance.len() != dims {
+                return Err(anyhow!(
+                    "Expected one `max_distance` or one per join column ({}), got {}",
+                    dims,
+                    max_distance.len()
+                ));
+            }
+            if scaled {
+                return Err(anyhow!(
+                    "Box joins are scaled by their per-column `max_distance`; \
+                     drop `weights` and `standardize`"
+                ));
+            }
+            if max_distance.iter().any(|m| m.is_nan() || *m <= 0.0) {
+                return Err(anyhow!(
+                    "Box joins need a positive `max_distance` for every column"
+                ));
+            }
+            // Dividing by the threshold can push a difference that sits
+            // exactly on it a few ulps past 1
+            return Ok(DistanceSpec {
+                metric: DistanceMetric::Chebyshev,
+                max_distance: 1.0 + 4.0 * f64::EPSILON,
+                scaling: Some(max_distance.iter().map(|m| 1.0 / m).collect()),
+                whitening: None,
+            });
+        }
+        let threshold = max_distance
+            .first()
+            .copied()
+            .ok_or_else(|| anyhow!("`max_distance` must not be empty"))?;
+
+        if scaled && (metric == DistanceMetric::Mahalanobis || metric.geodesic()) {
+            return Err(anyhow!(
+                "`weights` and `standardize` do not apply to {} distances",
+                method
+            ));
+        }
+
+        let whitening = if metric == DistanceMetric::Mahalanobis {
+            let cov = match cov {
+                Some(cov) => cov,
+                None => pooled_covariance(&pooled_rows(df1, df2, keys)?, dims)?,
+            };
+            Some(Whitening::new(&cov, dims)?)
+        } else {
+            None
+        };
+
+        let scaling = if scaled {
+            let mut factors = weights.unwrap_or_else(|| vec![1.0; dims]);
+            if let Some(standardize) = standardize {
+                // Rows with a missing value never match, so they do not
+                // count towards the spread either
+                let rows: Vec<Vec<f64>> = pooled_rows(df1, df2, keys)?
+                    .into_iter()
+                    .filter(|row| row.iter().all(|x| !x.is_nan()))
+                    .collect();
+                for (dim, factor) in factors.iter_mut().enumerate() {
+                    let values: Vec<f64> = rows.iter().map(|row| row[dim]).collect();
+                    // Constant columns are left as they are
+                    let spread = standardize.spread(&values);
+                    if spread.is_finite() && spread > 0.0 {
+                        *factor /= spread;
+                    }
+                }
+            }
+            Some(factors)
+        } else {
+            None
+        };
+
+        Ok(DistanceSpec {
+            metric,
+            max_distance: threshold,
+            scaling,
+            whitening,
+        })
+    }
+
+    pub fn transform(&self, rows: &mut [Vec<f64>]) {
+        if let Some(factors) = &self.scaling {
+            for row in rows.iter_mut() {
+                row.iter_mut().zip(factors).for_each(|(x, f)| *x *= f);
+            }
+        }
+        if let Some(whitening) = &self.whitening {
+            whitening.apply(rows);
+        }
+    }
+}
+
+// Join-column rows of `df1` followed by those of `df2`, if given
+fn pooled_rows(df1: &List, df2: Option<&List>, keys: &[(String, String)]) -> Result<Vec<Vec<f64>>> {
+    let cols1: Vec<&str> = keys.iter().map(|(lk, _)| lk.as_str()).collect();
+    let cols2: Vec<&str> = keys.iter().map(|(_, rk)| rk.as_str()).collect();
+    let mut rows = numeric_rows(df1, &cols1, "df1")?;
+    if let Some(df2) = df2 {
+        rows.extend(numeric_rows(df2, &cols2, "df2")?);
+    }
+    Ok(rows)
+}
+
+// Linear map that turns Mahalanobis distances into Euclidean ones. With the
+// covariance matrix factored as `L L'` (Cholesky), a row `x` maps to the
+// solution `z` of `L z = x`.
+#[derive(Debug, Clone)]
+pub struct Whitening {
+    chol: Vec<Vec<f64>>,
+}
+
+impl Whitening {
+    pub fn new(cov: &[f64], dims: usize) -> Result<Self> {
+        if cov.len() != dims * dims {
+            return Err(anyhow!(
+                "Covariance matrix must be {} x {} to match the join columns",
+                dims,
+                dims
+            ));
+        }
+        if cov.iter().any(|v| !v.is_finite()) {
+            return Err(anyhow!("Covariance matrix must be finite"));
+        }
+
+        let mut chol = vec![vec![0.0; dims]; dims];
+        for i in 0..dims {
+            for j in 0..=i {
+                let sum = cov[i * dims + j] - (0..j).map(|k| chol[i][k] * chol[j][k]).sum::<f64>();
+                if i == j {
+                    if sum <= 0.0 {
+                        return Err(anyhow!("Covariance matrix is not positive definite"));
+                    }
+                    chol[i][i] = sum.sqrt();
+                } else {
+                    chol[i][j] = sum / chol[j][j];
+                }
+            }
+        }
+        Ok(Whitening { chol })
+    }
+
+    pub fn apply(&self, rows: &mut [Vec<f64>]) {
+        for row in rows.iter_mut() {
+            for i in 0..row.len() {
+                let sum: f64 = (0..i).map(|k| self.chol[i][k] * row[k]).sum();
+                row[i] = (row[i] - sum) / self.chol[i][i];
+            }
+        }
+    }
+}
+
+// Sample covariance of the rows without missing values, row-major
+fn pooled_covariance(rows: &[Vec<f64>], dims: usize) -> Result<Vec<f64>> {
+    let complete: Vec<&Vec<f64>> = rows
+        .iter()
+        .filter(|row| row.iter().all(|x| !x.is_nan()))
+        .collect();
+    let n = complete.len();
+    if n <= dims {
+        return Err(anyhow!(
+            "Need more than {} complete rows to estimate the covariance matrix",
+            dims
+        ));
+    }
+
+    let means: Vec<f64> = (0..dims)
+        .map(|d| complete.iter().map(|row| row[d]).sum::<f64>() / n as f64)
+        .collect();
+    let mut cov = vec![0.0; dims * dims];
+    for row in &complete {
+        for i in 0..dims {
+            for j in 0..dims {
+                cov[i * dims + j] += (row[i] - means[i]) * (row[j] - means[j]);
+            }
+        }
+    }
+    cov.iter_mut().for_each(|v| *v /= (n - 1) as f64);
+    Ok(cov)
+}
+
+// Below this many candidate pairs, or above this many dimensions, comparing
+// every pair is faster than building and searching a KD-tree
+const BRUTE_FORCE_PAIRS: usize = 4096;
+const MAX_TREE_DIMS: usize = 16;
+
 pub fn filtered_distances(
     left: &[Vec<f64>],
     right: &[Vec<f64>],
     threshold: f64,
     metric: DistanceMetric,
+    select: Selection,
     pool: &rayon::ThreadPool,
 ) -> anyhow::Result<(Vec<usize>, Vec<usize>, Vec<f64>)> {
+    let dims = left.first().or(right.first()).map_or(0, |row| row.len());
+    let small = left.len().saturating_mul(right.len()) <= BRUTE_FORCE_PAIRS;
+    if !small && metric.geodesic() {
+        return geo::geodesic_distances(left, right, threshold, metric, select, pool);
+    }
+    if small || dims > MAX_TREE_DIMS || !metric.indexable() {
+        return brute_force_distances(left, right, threshold, metric, select, pool);
+    }
+
+    // Radius queries against a KD-tree over the right rows. Best matches
+    // first find the k-th nearest distance, then gather every match up to
+    // it so that ties are left to `keep_best`.
+    let tree = KdTree::new(right, metric);
     pool.install(|| {
-        let results: Result<Vec<(usize, usize, f64)>> = left
+        let triples: Vec<Vec<(usize, usize, f64)>> = left
             .par_iter()
             .enumerate()
-            .flat_map_iter(|(i, a)| {
-                right.iter().enumerate().filter_map(move |(j, b)| {
+            .map(|(i, a)| {
+                let radius = match select.top_k {
+                    Some(top_k) => match tree.kth_distance(a, top_k.k) {
+                        Some(kth) => kth.min(threshold),
+                        None => threshold,
+                    },
+                    None => threshold,
+                };
+                let mut row: Vec<(usize, usize, f64)> = tree
+                    .within(a, radius)
+                    .into_iter()
+                    .filter(|&(j, _)| select.pairing.compares_rows(i, j))
+                    .map(|(j, dist)| (i + 1, j + 1, dist))
+                    .collect();
+                row.sort_unstable_by_key(|&(_, j, _)| j);
+                keep_best(row, select.top_k)
+            })
+            .collect();
+
+        let (left_indices, right_indices, distances): (Vec<_>, Vec<_>, Vec<_>) =
+            triples.into_iter().flatten().multiunzip();
+        Ok((left_indices, right_indices, distances))
+    })
+}
+
+// Compare every left row with every right row
+fn brute_force_distances(
+    left: &[Vec<f64>],
+    right: &[Vec<f64>],
+    threshold: f64,
+    metric: DistanceMetric,
+    select: Selection,
+    pool: &rayon::ThreadPool,
+) -> anyhow::Result<(Vec<usize>, Vec<usize>, Vec<f64>)> {
+    pool.install(|| {
+        let results: Result<Vec<Vec<(usize, usize, f64)>>> = left
+            .par_iter()
+            .enumerate()
+            .map(|(i, a)| {
+                let mut row = Vec::new();
+                for (j, b) in right.iter().enumerate() {
+                    // A self-join compares each pair of rows once
+                    if !select.pairing.compares_rows(i, j) {
+                        continue;
+                    }
                     if a.len() != b.len() {
-                        return Some(Err(anyhow!(
+                        return Err(anyhow!(
                             "Vector length mismatch at left[{}] and right[{}]",
                             i,
                             j
-                        )));
+                        ));
                     }
 
-                    let dist = match metric {
-                        DistanceMetric::Euclidean => a
-                            .iter()
-                            .zip(b.iter())
-                            .map(|(x, y)| (x - y).powi(2))
-                            .sum::<f64>()
-                            .sqrt(),
-                        DistanceMetric::Manhattan => a
-                            .iter()
-                            .zip(b.iter())
-                            .map(|(x, y)| (x - y).abs())
-                            .sum::<f64>(),
-                    };
-
+                    let dist = metric.distance(a, b);
                     if dist <= threshold {
-                        Some(Ok((i + 1, j + 1, dist)))
-                    } else {
-                        None
+                        row.push((i + 1, j + 1, dist));
                     }
-                })
+                }
+                Ok(keep_best(row, select.top_k))
             })
             .collect();
 
         match results {
             Ok(triples) => {
                 let (left_indices, right_indices, distances): (Vec<_>, Vec<_>, Vec<_>) =
-                    triples.into_iter().multiunzip();
+                    triples.into_iter().flatten().multiunzip();
                 Ok((left_indices, right_indices, distances))
             }
             Err(e) => Err(e),
@@ -35,10 +35,10 @@ pub fn fuzzy_indices_interval_real(
     let (left_start_key, right_start_key) = &keys[0];
     let (left_end_key, right_end_key) = &keys[1];
 
-    let left_start = any_numeric_to_vec64(df1, left_start_key)?;
-    let left_end = any_numeric_to_vec64(df1, left_end_key)?;
-    let right_start = any_numeric_to_vec64(df2, right_start_key)?;
-    let right_end = any_numeric_to_vec64(df2, right_end_key)?;
+    let left_start = any_numeric_to_vec64(df1, left_start_key, "df1")?;
+    let left_end = any_numeric_to_vec64(df1, left_end_key, "df1")?;
+    let right_start = any_numeric_to_vec64(df2, right_start_key, "df2")?;
+    let right_end = any_numeric_to_vec64(df2, right_end_key, "df2")?;
 
     if left_start.len() != left_end.len() || right_start.len() != right_end.len() {
         return Err(anyhow!("Start and end columns must have equal lengths"));
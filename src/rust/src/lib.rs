@@ -2,143 +2,231 @@ use anyhow::{anyhow, Result};
 use core::f64;
 use extendr_api::prelude::*;
 
+pub mod assign;
+pub mod block;
+pub mod composite;
+pub mod dedupe;
 pub mod difference;
 pub mod distance;
 pub mod interval;
+pub mod linkage;
 pub mod merge;
+pub mod options;
 pub mod regex;
 pub mod string;
+pub mod topk;
 pub mod utils;
 
-use crate::difference::{difference_join, difference_pairs};
-use crate::distance::fuzzy_indices_dist;
+use crate::assign::assign_matches;
+use crate::block::{blocked_matches, Blocks};
+use crate::dedupe::{cluster_ids, Pairing};
+use crate::difference::asof::{asof_matches, Direction};
+use crate::difference::DifferenceSpec;
+use crate::distance::{fuzzy_indices_dist, DistanceSpec};
 use crate::interval::integer::fuzzy_indices_interval_int;
 use crate::interval::real::fuzzy_indices_interval_real;
+use crate::linkage::linkage_join;
 use crate::merge::dispatch_join;
 use crate::merge::DistanceData;
+use crate::options::{MatchOptions, Options};
 use crate::regex::{regex_join, regex_pairs};
-use crate::string::string_join;
-use crate::utils::get_pool;
+use crate::string::{extract_keys, string_dedupe, string_join};
+use crate::topk::Selection;
+use crate::utils::{any_numeric_to_vec64, df_nrows, get_pool};
+
+// Join and dedupe entry points take their settings as one named list,
+// `options`; see `options.rs`
 
 #[extendr]
 pub fn fozzie_string_join_rs(
     df1: List,
     df2: List,
     by: List,
-    method: String,
     how: String,
-    max_distance: f64,
     distance_col: Option<String>,
-    q: Option<i32>,
-    max_prefix: Option<i32>,
-    prefix_weight: Option<f64>,
+    options: List,
     nthread: Option<usize>,
 ) -> Result<List> {
     let result = string_join(
         df1,
         df2,
         by,
-        method,
         how,
-        max_distance,
         distance_col,
-        q,
-        max_prefix,
-        prefix_weight,
+        Options::new(options),
         nthread,
     )
     .map_err(|e| anyhow!("Error in string join: {e}!"))?;
     Ok(result)
 }
 
+#[extendr]
+pub fn fozzie_linkage_join_rs(
+    df1: List,
+    df2: List,
+    by: List,
+    how: String,
+    distance_col: Option<String>,
+    options: List,
+    nthread: Option<usize>,
+) -> Result<List> {
+    let result = linkage_join(
+        df1,
+        df2,
+        by,
+        how,
+        distance_col,
+        Options::new(options),
+        nthread,
+    )
+    .map_err(|e| anyhow!("Error in linkage join: {e}!"))?;
+    Ok(result)
+}
+
 #[extendr]
 pub fn fozzie_difference_join_rs(
     df1: List,
     df2: List,
     by: List,
     how: String,
-    max_distance: f64,
     distance_col: Option<String>,
+    options: List,
     nthread: Option<usize>,
 ) -> Result<List> {
     let pool = get_pool(nthread)?;
+    let options = Options::new(options);
+    let matching = MatchOptions::new(&options)?;
+    let spec = DifferenceSpec::new(&options, &by, matching.top_k)?;
+    let matches = spec.pairs(
+        &df1,
+        &df2,
+        matching.block_by.as_ref(),
+        Pairing::Cross,
+        &pool,
+    )?;
 
-    let keys: Vec<(String, String)> = by
-        .iter()
-        .map(|(left_key, val)| {
-            let right_key = val
-                .as_string_vector()
-                .ok_or_else(|| anyhow!("Missing string vector for key '{}'", left_key))?;
-            Ok((left_key.to_string(), right_key[0].clone()))
-        })
-        .collect::<Result<_>>()?;
-
-    let (mut idxs1, mut idxs2, dists) =
-        difference_join(&df1, &df2, keys[0].clone(), max_distance, &pool)
-            .map_err(|e| anyhow!("Failed initial difference join: {}", e))?;
+    // Assignment ranks by the composite score when there is one, which
+    // comes last after one distance per key column
+    let scored = matches.2.len() > by.len();
+    let cost_col = if scored { by.len() } else { 0 };
+    let (idxs1, idxs2, mut dists) = assign_matches(matches, cost_col, matching.assignment)?;
 
-    let out: List = if keys.len() == 1 {
-        let dists = DistanceData::Single(&dists);
-        dispatch_join(
-            how.as_str(),
-            &df1,
-            &df2,
-            idxs1,
-            idxs2,
-            distance_col,
-            dists,
-            by,
-        )
+    let scores;
+    let dists = if scored {
+        scores = dists.pop().unwrap_or_default();
+        DistanceData::Scored(&dists, &scores)
+    } else if by.len() == 1 {
+        DistanceData::Single(&dists[0])
     } else {
-        let mut dists = vec![dists];
-        for bypair in &keys[1..] {
-            let (a, b, c) = difference_pairs(
-                &df1,
-                &idxs1,
-                &df2,
-                &idxs2,
-                bypair,
-                &dists,
-                max_distance,
-                &pool,
-            )
-            .map_err(|e| anyhow!("Failed difference_pairs for {:?}: {}", bypair, e))?;
-            idxs1 = a;
-            idxs2 = b;
-            dists = c;
-        }
-        let dists = DistanceData::Matrix(&dists);
-        dispatch_join(
-            how.as_str(),
-            &df1,
-            &df2,
-            idxs1,
-            idxs2,
-            distance_col,
-            dists,
-            by,
-        )
+        DistanceData::Matrix(&dists)
     };
 
+    let out = dispatch_join(
+        how.as_str(),
+        &df1,
+        &df2,
+        idxs1,
+        idxs2,
+        distance_col,
+        dists,
+        by,
+    );
     Ok(out)
 }
 
+#[extendr]
+pub fn fozzie_asof_join_rs(
+    df1: List,
+    df2: List,
+    by: List,
+    how: String,
+    distance_col: Option<String>,
+    options: List,
+    nthread: Option<usize>,
+) -> Result<List> {
+    let pool = get_pool(nthread)?;
+    let options = Options::new(options);
+    let direction: String = options
+        .get("direction")?
+        .ok_or_else(|| anyhow!("Must provide `direction`"))?;
+    let direction = Direction::new(&direction)?;
+    let tolerance: Option<f64> = options.get("tolerance")?;
+    if tolerance.is_some_and(|t| t.is_nan() || t < 0.0) {
+        return Err(anyhow!("`tolerance` must be a non-negative number"));
+    }
+    let keys = extract_keys(&by)?;
+    let [(lk, rk)] = keys.as_slice() else {
+        return Err(anyhow!("As-of joins take exactly one key column"));
+    };
+
+    let vec1 = any_numeric_to_vec64(&df1, lk, "df1")?;
+    let vec2 = any_numeric_to_vec64(&df2, rk, "df2")?;
+
+    // Exact-match groups reuse the blocking partition; without them every
+    // row is in one group
+    let groups = match options.list("group_by")? {
+        Some(group_by) => Blocks::new(&df1, &df2, &group_by)?.groups,
+        None => vec![((1..=vec1.len()).collect(), (1..=vec2.len()).collect())],
+    };
+
+    let (idxs1, idxs2, dists) = asof_matches(&vec1, &vec2, &groups, direction, tolerance, &pool);
+
+    let dists = DistanceData::Single(&dists[0]);
+    let joined = dispatch_join(
+        how.as_str(),
+        &df1,
+        &df2,
+        idxs1,
+        idxs2,
+        distance_col,
+        dists,
+        by,
+    );
+    Ok(joined)
+}
+
 #[extendr]
 pub fn fozzie_distance_join_rs(
     df1: List,
     df2: List,
     by: List,
-    method: String,
     how: String,
-    max_distance: f64,
     distance_col: Option<String>,
+    options: List,
     nthread: Option<usize>,
 ) -> Result<List> {
     let pool = get_pool(nthread)?;
+    let options = Options::new(options);
+    let matching = MatchOptions::new(&options)?;
+    let keys = extract_keys(&by)?;
+    // Scales and covariances come from the full frames, not each block
+    let spec = DistanceSpec::new(&options, &df1, Some(&df2), &keys)?;
 
-    let (idxs1, idxs2, dists) = fuzzy_indices_dist(&df1, &df2, &by, &method, max_distance, &pool)
-        .map_err(|e| anyhow!("Error when finding fuzzy matches: {e}"))?;
-    let dists = DistanceData::Single(&dists);
+    let cols1: Vec<&str> = keys.iter().map(|(lk, _)| lk.as_str()).collect();
+    let cols2: Vec<&str> = keys.iter().map(|(_, rk)| rk.as_str()).collect();
+    let matches = blocked_matches(
+        &df1,
+        &df2,
+        matching.block_by.as_ref(),
+        &cols1,
+        &cols2,
+        1,
+        |df1, df2| {
+            let (idxs1, idxs2, dists) = fuzzy_indices_dist(
+                df1,
+                df2,
+                &by,
+                &spec,
+                Selection::top_k(matching.top_k.as_ref()),
+                &pool,
+            )
+            .map_err(|e| anyhow!("Error when finding fuzzy matches: {e}"))?;
+            Ok((idxs1, idxs2, vec![dists]))
+        },
+    )?;
+    let (idxs1, idxs2, dists) = assign_matches(matches, 0, matching.assignment)?;
+
+    let dists = DistanceData::Single(&dists[0]);
     let joined = dispatch_join(
         how.as_str(),
         &df1,
@@ -152,6 +240,65 @@ pub fn fozzie_distance_join_rs(
     Ok(joined)
 }
 
+#[extendr]
+pub fn fozzie_string_dedupe_rs(
+    df: List,
+    by: List,
+    options: List,
+    nthread: Option<usize>,
+) -> Result<Vec<i32>> {
+    let result = string_dedupe(df, by, Options::new(options), nthread)
+        .map_err(|e| anyhow!("Error in string dedupe: {e}!"))?;
+    Ok(result)
+}
+
+#[extendr]
+pub fn fozzie_difference_dedupe_rs(
+    df: List,
+    by: List,
+    options: List,
+    nthread: Option<usize>,
+) -> Result<Vec<i32>> {
+    let pool = get_pool(nthread)?;
+    let options = Options::new(options);
+    let matching = MatchOptions::new(&options)?;
+    let spec = DifferenceSpec::new(&options, &by, None)?;
+    let (idxs1, idxs2, _) = spec.pairs(
+        &df,
+        &df,
+        matching.block_by.as_ref(),
+        Pairing::SelfJoin,
+        &pool,
+    )?;
+
+    Ok(cluster_ids(df_nrows(&df), &idxs1, &idxs2))
+}
+
+#[extendr]
+pub fn fozzie_distance_dedupe_rs(
+    df: List,
+    by: List,
+    options: List,
+    nthread: Option<usize>,
+) -> Result<Vec<i32>> {
+    let pool = get_pool(nthread)?;
+    let options = Options::new(options);
+    let matching = MatchOptions::new(&options)?;
+    let keys = extract_keys(&by)?;
+    let spec = DistanceSpec::new(&options, &df, None, &keys)?;
+
+    let cols: Vec<&str> = keys.iter().map(|(lk, _)| lk.as_str()).collect();
+    let block_by = matching.block_by.as_ref();
+    let (idxs1, idxs2, _) = blocked_matches(&df, &df, block_by, &cols, &cols, 1, |df1, df2| {
+        let (idxs1, idxs2, dists) =
+            fuzzy_indices_dist(df1, df2, &by, &spec, Selection::self_join(), &pool)
+                .map_err(|e| anyhow!("Error when finding fuzzy matches: {e}"))?;
+        Ok((idxs1, idxs2, vec![dists]))
+    })?;
+
+    Ok(cluster_ids(df_nrows(&df), &idxs1, &idxs2))
+}
+
 #[extendr]
 pub fn fozzie_interval_join_rs(
     df1: List,
@@ -247,8 +394,13 @@ fn get_nthread_default() -> usize {
 extendr_module! {
     mod fozziejoin;
     fn fozzie_string_join_rs;
+    fn fozzie_linkage_join_rs;
     fn fozzie_difference_join_rs;
+    fn fozzie_asof_join_rs;
     fn fozzie_distance_join_rs;
+    fn fozzie_string_dedupe_rs;
+    fn fozzie_difference_dedupe_rs;
+    fn fozzie_distance_dedupe_rs;
     fn fozzie_interval_join_rs;
     fn fozzie_regex_join_rs;
     fn get_nthread_default;
@@ -0,0 +1,177 @@
+// Expectation-maximisation for the Fellegi-Sunter model. Every candidate
+// pair is either a match (with probability `lambda`) or a non-match, and the
+// fields agree independently given that class: `m[f][l]` is the probability
+// that field `f` is at level `l` for a match and `u[f][l]` the same for a
+// non-match. Pairs with the same agreement pattern are indistinguishable, so
+// the estimation runs over distinct patterns weighted by their counts.
+
+use anyhow::{anyhow, Result};
+
+// Keeps probabilities away from 0 and 1 so that log weights stay finite
+const EPS: f64 = 1e-6;
+
+#[derive(Debug, Clone)]
+pub struct EmSettings {
+    pub prior: f64,
+    pub max_iter: usize,
+    pub tol: f64,
+}
+
+impl EmSettings {
+    pub fn new(prior: f64, max_iter: i32, tol: f64) -> Result<Self> {
+        if !(prior > 0.0 && prior < 1.0) {
+            return Err(anyhow!("`prior` must be strictly between 0 and 1"));
+        }
+        let max_iter =
+            usize::try_from(max_iter).map_err(|_| anyhow!("`max_iter` must be non-negative"))?;
+        if tol.is_nan() || tol <= 0.0 {
+            return Err(anyhow!("`tol` must be positive"));
+        }
+        Ok(EmSettings {
+            prior,
+            max_iter,
+            tol,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FellegiSunter {
+    pub lambda: f64,
+    pub m: Vec<Vec<f64>>,
+    pub u: Vec<Vec<f64>>,
+    pub iterations: usize,
+    pub converged: bool,
+}
+
+impl FellegiSunter {
+    // Starting values: matches mostly agree, favouring the closest levels,
+    // and non-matches mostly disagree. `n_levels[f]` counts the levels of
+    // field `f`, the last one being disagreement.
+    fn initial(n_levels: &[usize], prior: f64) -> Self {
+        let mut m = Vec::with_capacity(n_levels.len());
+        let mut u = Vec::with_capacity(n_levels.len());
+
+        for &n in n_levels {
+            let agree = n - 1;
+            let halves: Vec<f64> = (0..agree).map(|l| 0.5f64.powi(l as i32)).collect();
+            let total: f64 = halves.iter().sum();
+
+            let mut mf: Vec<f64> = halves.iter().map(|h| 0.9 * h / total).collect();
+            mf.push(0.1);
+            let mut uf = vec![0.1 / agree as f64; agree];
+            uf.push(0.9);
+
+            m.push(mf);
+            u.push(uf);
+        }
+
+        FellegiSunter {
+            lambda: prior,
+            m,
+            u,
+            iterations: 0,
+            converged: false,
+        }
+    }
+
+    // Fit the model to `patterns`, each paired with the number of candidate
+    // pairs sharing it. `None` marks a field missing on either side, which
+    // carries no evidence either way.
+    pub fn estimate(
+        patterns: &[(Vec<Option<usize>>, usize)],
+        n_levels: &[usize],
+        settings: &EmSettings,
+    ) -> Self {
+        let mut model = Self::initial(n_levels, settings.prior);
+        let total: f64 = patterns.iter().map(|(_, c)| *c as f64).sum();
+        if total == 0.0 {
+            return model;
+        }
+
+        while model.iterations < settings.max_iter {
+            // E-step: posterior match probability of each pattern
+            let g: Vec<f64> = patterns.iter().map(|(p, _)| model.posterior(p)).collect();
+
+            // M-step: expected level counts within each class
+            let mut m_counts: Vec<Vec<f64>> = n_levels.iter().map(|&n| vec![0.0; n]).collect();
+            let mut u_counts = m_counts.clone();
+            let mut matched = 0.0;
+
+            for ((pattern, count), gi) in patterns.iter().zip(&g) {
+                let c = *count as f64;
+                matched += c * gi;
+                for (f, level) in pattern.iter().enumerate() {
+                    if let Some(l) = level {
+                        m_counts[f][*l] += c * gi;
+                        u_counts[f][*l] += c * (1.0 - gi);
+                    }
+                }
+            }
+
+            let lambda = (matched / total).clamp(EPS, 1.0 - EPS);
+            let m: Vec<Vec<f64>> = m_counts.iter().map(|x| normalise(x)).collect();
+            let u: Vec<Vec<f64>> = u_counts.iter().map(|x| normalise(x)).collect();
+
+            let change = m
+                .iter()
+                .flatten()
+                .zip(model.m.iter().flatten())
+                .chain(u.iter().flatten().zip(model.u.iter().flatten()))
+                .map(|(a, b)| (a - b).abs())
+                .fold((lambda - model.lambda).abs(), f64::max);
+
+            model.lambda = lambda;
+            model.m = m;
+            model.u = u;
+            model.iterations += 1;
+
+            if change < settings.tol {
+                model.converged = true;
+                break;
+            }
+        }
+
+        model
+    }
+
+    // Log-2 likelihood ratio of a match against a non-match
+    pub fn weight(&self, pattern: &[Option<usize>]) -> f64 {
+        pattern
+            .iter()
+            .enumerate()
+            .filter_map(|(f, level)| level.map(|l| (self.m[f][l] / self.u[f][l]).log2()))
+            .sum()
+    }
+
+    // Posterior probability that a pair with this pattern is a match
+    pub fn posterior(&self, pattern: &[Option<usize>]) -> f64 {
+        let prior_odds = (self.lambda / (1.0 - self.lambda)).log2();
+        let log_odds = prior_odds + self.weight(pattern);
+        1.0 / (1.0 + (-log_odds * std::f64::consts::LN_2).exp())
+    }
+
+    // Per-level weights of one field, for reporting
+    pub fn level_weights(&self, field: usize) -> Vec<f64> {
+        self.m[field]
+            .iter()
+            .zip(&self.u[field])
+            .map(|(m, u)| (m / u).log2())
+            .collect()
+    }
+}
+
+// Turn expected counts into probabilities, with every level kept above `EPS`
+fn normalise(counts: &[f64]) -> Vec<f64> {
+    let total: f64 = counts.iter().sum();
+    let n = counts.len() as f64;
+    let probs: Vec<f64> = if total > 0.0 {
+        counts.iter().map(|c| c / total).collect()
+    } else {
+        vec![1.0 / n; counts.len()]
+    };
+
+    let clamped: Vec<f64> = probs.iter().map(|p| p.max(EPS)).collect();
+    let total: f64 = clamped.iter().sum();
+    clamped.iter().map(|p| p / total).collect()
+}
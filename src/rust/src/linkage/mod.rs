@@ -0,0 +1,296 @@
+// Fellegi-Sunter probabilistic record linkage. Candidate pairs come from the
+// first field's string join, as for multi-column joins. Every candidate
+// agrees on that field, so it only blocks and is left out of the model: it
+// would never reach disagreement, leaving its m and u degenerate. Every other
+// field puts each pair on an agreement level according to its distance: level 0
+// for distances within the first threshold in `levels`, level 1 within the
+// second, and so on, with one final level for disagreement. The m and u
+// probabilities of every level are estimated by EM (see `em`), and pairs are
+// kept when their posterior match probability reaches `min_posterior`.
+
+pub mod em;
+
+use crate::assign::{assign_matches, Assignment};
+use crate::linkage::em::{EmSettings, FellegiSunter};
+use crate::merge::{dispatch_join, DistanceData};
+use crate::options::Options;
+use crate::string::extract_keys;
+use crate::string::joinmethod::{method_from_spec, JoinMethod};
+use crate::topk::Selection;
+use crate::utils::get_pool;
+
+use anyhow::{anyhow, Result};
+use extendr_api::prelude::*;
+use rustc_hash::FxHashMap;
+
+// One linked field: how it is compared and where its agreement levels end
+pub struct Field {
+    pub join_method: JoinMethod,
+    pub thresholds: Vec<f64>,
+}
+
+impl Field {
+    // Read a method spec, plus its optional `levels`. Without them the field
+    // only distinguishes agreement within `max_distance` from disagreement.
+    pub fn from_spec(spec: &Robj) -> Result<Self> {
+        let join_method = method_from_spec(spec, None)?;
+        let max_distance = join_method.max_distance();
+
+        let levels = List::try_from(spec)
+            .ok()
+            .and_then(|x| x.dollar("levels").ok())
+            .filter(|x| !x.is_null());
+        let thresholds = match levels {
+            Some(x) => x
+                .as_real_vector()
+                .or_else(|| {
+                    x.as_integer_vector()
+                        .map(|v| v.into_iter().map(|i| i as f64).collect())
+                })
+                .ok_or_else(|| anyhow!("`levels` must be numeric"))?,
+            None => vec![max_distance],
+        };
+
+        if thresholds.is_empty() || thresholds.iter().any(|t| t.is_nan()) {
+            return Err(anyhow!("`levels` must hold at least one non-missing value"));
+        }
+        if thresholds.windows(2).any(|w| w[0] >= w[1]) {
+            return Err(anyhow!("`levels` must be strictly increasing"));
+        }
+        if thresholds[thresholds.len() - 1] > max_distance {
+            return Err(anyhow!(
+                "`levels` must not exceed the field's `max_distance` ({})",
+                max_distance
+            ));
+        }
+
+        Ok(Field {
+            join_method,
+            thresholds,
+        })
+    }
+
+    // Agreement levels plus the final disagreement level
+    pub fn n_levels(&self) -> usize {
+        self.thresholds.len() + 1
+    }
+
+    pub fn level(&self, dist: f64) -> usize {
+        self.thresholds
+            .iter()
+            .position(|t| dist <= *t)
+            .unwrap_or(self.thresholds.len())
+    }
+
+    pub fn labels(&self) -> Vec<String> {
+        self.thresholds
+            .iter()
+            .map(|t| format!("<= {}", t))
+            .chain(std::iter::once("disagree".to_string()))
+            .collect()
+    }
+}
+
+pub fn linkage_join(
+    df1: List,
+    df2: List,
+    by: List,
+    how: String,
+    distance_col: Option<String>,
+    options: Options,
+    nthread: Option<usize>,
+) -> Result<List> {
+    let keys: Vec<(String, String)> = extract_keys(&by)?;
+    let pool = get_pool(nthread)?;
+    let specs = options
+        .list("specs")?
+        .ok_or_else(|| anyhow!("Must provide one method spec per field"))?;
+    let settings = EmSettings::new(
+        options.get("prior")?.unwrap_or(f64::NAN),
+        options.get("max_iter")?.unwrap_or(-1),
+        options.get("tol")?.unwrap_or(f64::NAN),
+    )?;
+    let assignment = Assignment::new(options.get::<String>("assign")?.as_deref())?;
+
+    let min_posterior: f64 = options
+        .get::<f64>("min_posterior")?
+        .filter(|x| !x.is_nan())
+        .ok_or_else(|| anyhow!("`min_posterior` must not be NA"))?;
+    if specs.len() != keys.len() {
+        return Err(anyhow!(
+            "Expected {} method specs (one per join column), got {}",
+            keys.len(),
+            specs.len()
+        ));
+    }
+    if keys.len() < 2 {
+        return Err(anyhow!(
+            "Linkage needs at least two fields: the first selects candidate pairs and the others are weighed"
+        ));
+    }
+    let fields: Vec<Field> = specs
+        .values()
+        .map(|spec| Field::from_spec(&spec))
+        .collect::<Result<_>>()?;
+
+    let (left_key, right_key) = &keys[0];
+    let mut matchdat = fields[0].join_method.fuzzy_indices(
+        &df1,
+        left_key,
+        &df2,
+        right_key,
+        Selection::default(),
+        &pool,
+    )?;
+    matchdat.sort_by_key(|&(i, j, _)| (i, j));
+    let (idxs1, idxs2): (Vec<usize>, Vec<usize>) =
+        matchdat.into_iter().map(|(i, j, _)| (i, j)).unzip();
+
+    // One agreement pattern per candidate pair, over the fields after the
+    // candidate-generating one
+    let (keys, fields) = (&keys[1..], &fields[1..]);
+    let mut patterns: Vec<Vec<Option<usize>>> = vec![Vec::with_capacity(keys.len()); idxs1.len()];
+    for (bypair, field) in keys.iter().zip(fields) {
+        let levels = agreement_levels(&df1, &idxs1, &df2, &idxs2, bypair, field, &pool)?;
+        for (pattern, level) in patterns.iter_mut().zip(levels) {
+            pattern.push(level);
+        }
+    }
+
+    let mut counts: FxHashMap<&Vec<Option<usize>>, usize> = FxHashMap::default();
+    for pattern in &patterns {
+        *counts.entry(pattern).or_insert(0) += 1;
+    }
+    let distinct: Vec<(Vec<Option<usize>>, usize)> =
+        counts.into_iter().map(|(p, c)| (p.clone(), c)).collect();
+
+    let n_levels: Vec<usize> = fields.iter().map(Field::n_levels).collect();
+    let model = FellegiSunter::estimate(&distinct, &n_levels, &settings);
+
+    let scored: FxHashMap<&Vec<Option<usize>>, (f64, f64)> = distinct
+        .iter()
+        .map(|(p, _)| (p, (model.weight(p), model.posterior(p))))
+        .collect();
+
+    let mut keep1 = Vec::new();
+    let mut keep2 = Vec::new();
+    let mut weights = Vec::new();
+    let mut posteriors = Vec::new();
+    for ((i, j), pattern) in idxs1.iter().zip(&idxs2).zip(&patterns) {
+        let (weight, posterior) = scored[pattern];
+        if posterior >= min_posterior {
+            keep1.push(*i);
+            keep2.push(*j);
+            weights.push(weight);
+            posteriors.push(posterior);
+        }
+    }
+
+    // One-to-one links prefer the highest match weights, i.e. the smallest
+    // shortfall from the best one
+    let best = weights.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+    let costs: Vec<f64> = weights.iter().map(|w| best - w).collect();
+    let (keep1, keep2, dists) = assign_matches(
+        (keep1, keep2, vec![costs, weights, posteriors]),
+        0,
+        assignment,
+    )?;
+    let (weights, posteriors) = (&dists[1], &dists[2]);
+
+    let params = model_summary(&model, fields, keys)?;
+    let dists = DistanceData::Linkage(weights, posteriors);
+    let mut out = dispatch_join(
+        how.as_str(),
+        &df1,
+        &df2,
+        keep1,
+        keep2,
+        distance_col,
+        dists,
+        by,
+    );
+    out.set_attrib("linkage", params)
+        .map_err(|e| anyhow!("Failed to attach linkage parameters: {e}"))?;
+    Ok(out)
+}
+
+// Agreement level of every candidate pair on one field, `None` where either
+// value is missing
+fn agreement_levels(
+    df1: &List,
+    idxs1: &Vec<usize>,
+    df2: &List,
+    idxs2: &Vec<usize>,
+    by: &(String, String),
+    field: &Field,
+    pool: &rayon::ThreadPool,
+) -> Result<Vec<Option<usize>>> {
+    let lk = by.0.as_str();
+    let rk = by.1.as_str();
+
+    let vec1_binding = df1
+        .dollar(lk)
+        .map_err(|_| anyhow!("Missing column '{}' in df1", lk))?
+        .slice(idxs1)
+        .map_err(|_| anyhow!("Failed to slice df1 column '{}'", lk))?;
+    let vec2_binding = df2
+        .dollar(rk)
+        .map_err(|_| anyhow!("Missing column '{}' in df2", rk))?
+        .slice(idxs2)
+        .map_err(|_| anyhow!("Failed to slice df2 column '{}'", rk))?;
+
+    let strs1 = Strings::try_from(&vec1_binding)
+        .map_err(|_| anyhow!("Failed to convert df1 column '{}' to string vector", lk))?;
+    let strs2 = Strings::try_from(&vec2_binding)
+        .map_err(|_| anyhow!("Failed to convert df2 column '{}' to string vector", rk))?;
+    let vec1: Vec<&str> = strs1.iter().map(|s| s.as_str()).collect();
+    let vec2: Vec<&str> = strs2.iter().map(|s| s.as_str()).collect();
+
+    // Pairs that fail the comparison disagree; the others get the level of
+    // their distance
+    let disagree = field.thresholds.len();
+    let mut levels: Vec<Option<usize>> = vec![Some(disagree); idxs1.len()];
+    let (idxs0, dists) = field.join_method.compare_pairs(&vec1, &vec2, pool)?;
+    for (i, d) in idxs0.into_iter().zip(dists) {
+        levels[i] = Some(field.level(d));
+    }
+
+    for (i, (a, b)) in strs1.iter().zip(strs2.iter()).enumerate() {
+        if a.is_na() || b.is_na() {
+            levels[i] = None;
+        }
+    }
+
+    Ok(levels)
+}
+
+// Estimated parameters, returned to R as the `linkage` attribute
+fn model_summary(
+    model: &FellegiSunter,
+    fields: &[Field],
+    keys: &[(String, String)],
+) -> Result<List> {
+    let per_field: Vec<Robj> = fields
+        .iter()
+        .enumerate()
+        .map(|(f, field)| {
+            list!(
+                level = field.labels(),
+                m = model.m[f].clone(),
+                u = model.u[f].clone(),
+                weight = model.level_weights(f)
+            )
+            .into_robj()
+        })
+        .collect();
+    let names: Vec<&str> = keys.iter().map(|(lk, _)| lk.as_str()).collect();
+    let per_field = List::from_names_and_values(names, per_field)
+        .map_err(|e| anyhow!("Failed to build linkage parameters: {e}"))?;
+
+    Ok(list!(
+        lambda = model.lambda,
+        iterations = model.iterations as i32,
+        converged = model.converged,
+        fields = per_field
+    ))
+}
@@ -68,8 +68,8 @@ impl Merge {
                     names.push(name);
                     combined.push(padded.into_robj());
                 }
-                DistanceData::Matrix(mat) => {
-                    let (dist_names, dist_cols) = build_distance_columns(mat, &by, &colname);
+                DistanceData::Matrix(_) | DistanceData::Scored(..) | DistanceData::Linkage(..) => {
+                    let (dist_names, dist_cols) = build_distance_columns(&dist, &by, &colname);
                     for (vals, name) in dist_cols.into_iter().zip(dist_names) {
                         let mut padded = vals.as_real_slice().unwrap().to_vec();
                         padded.extend(vec![f64::NAN; unmatched_lhs + unmatched_rhs]);
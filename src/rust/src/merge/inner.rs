@@ -64,8 +64,8 @@ impl Merge {
                     names.push(name);
                     values.push(col);
                 }
-                DistanceData::Matrix(mat) => {
-                    let (dist_names, dist_cols) = build_distance_columns(mat, &by, &colname);
+                DistanceData::Matrix(_) | DistanceData::Scored(..) | DistanceData::Linkage(..) => {
+                    let (dist_names, dist_cols) = build_distance_columns(&dist, &by, &colname);
                     names.extend(dist_names);
                     values.extend(dist_cols);
                 }
@@ -93,16 +93,34 @@ pub fn subset_and_label(df: &List, indices: &[usize]) -> (Vec<String>, Vec<Robj>
 
 /// Helper to construct distance columns
 pub fn build_distance_columns(
-    dist: &[Vec<f64>],
+    dist: &DistanceData,
     by: &List,
     distance_col: &str,
 ) -> (Vec<String>, Vec<Robj>) {
-    let mut names = Vec::with_capacity(dist.len());
-    let mut columns = Vec::with_capacity(dist.len());
+    let (mat, score) = match dist {
+        DistanceData::Single(vec) => {
+            let (name, col) = build_single_distance_column(vec, distance_col);
+            return (vec![name], vec![col]);
+        }
+        DistanceData::Matrix(mat) => (mat, None),
+        DistanceData::Scored(mat, score) => (mat, Some(score)),
+        DistanceData::Linkage(weights, posteriors) => {
+            return (
+                vec![
+                    format!("{}_weight", distance_col),
+                    format!("{}_posterior", distance_col),
+                ],
+                vec![weights.into_robj(), posteriors.into_robj()],
+            );
+        }
+    };
+
+    let mut names = Vec::with_capacity(mat.len() + 1);
+    let mut columns = Vec::with_capacity(mat.len() + 1);
 
-    let ndist = dist.len();
-    for (x, (y, z)) in dist.iter().zip(by.iter()) {
-        let cname = if ndist == 1 {
+    let ndist = mat.len();
+    for (x, (y, z)) in mat.iter().zip(by.iter()) {
+        let cname = if ndist == 1 && score.is_none() {
             distance_col.to_string()
         } else {
             format!(
@@ -116,6 +134,11 @@ pub fn build_distance_columns(
         columns.push(x.into_robj());
     }
 
+    if let Some(score) = score {
+        names.push(format!("{}_score", distance_col));
+        columns.push(score.into_robj());
+    }
+
     (names, columns)
 }
 
@@ -138,4 +161,8 @@ pub fn pad_column(col: &Robj, pad_len: usize) -> Robj {
 pub enum DistanceData<'a> {
     Single(&'a Vec<f64>),
     Matrix(&'a Vec<Vec<f64>>),
+    // Per-column distances plus the weighted composite score of each pair
+    Scored(&'a Vec<Vec<f64>>, &'a Vec<f64>),
+    // Fellegi-Sunter match weight and posterior match probability of each pair
+    Linkage(&'a Vec<f64>, &'a Vec<f64>),
 }
@@ -0,0 +1,60 @@
+// Join settings passed from R as one named list rather than one argument
+// each. Entries that are missing or `NULL` are unset, and each entry converts
+// as it would as an argument of its own, so `NA` scalars are unset too.
+
+use anyhow::{anyhow, Result};
+use extendr_api::prelude::*;
+
+use crate::assign::Assignment;
+use crate::topk::TopK;
+
+pub struct Options(List);
+
+impl Options {
+    pub fn new(list: List) -> Self {
+        Options(list)
+    }
+
+    fn field(&self, name: &str) -> Option<Robj> {
+        self.0.dollar(name).ok().filter(|x| !x.is_null())
+    }
+
+    pub fn get<T>(&self, name: &str) -> Result<Option<T>>
+    where
+        for<'r> Option<T>: TryFrom<&'r Robj, Error = extendr_api::Error>,
+    {
+        match self.field(name) {
+            Some(x) => Option::<T>::try_from(&x).map_err(|e| anyhow!("Invalid `{}`: {}", name, e)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn list(&self, name: &str) -> Result<Option<List>> {
+        self.field(name)
+            .map(|x| List::try_from(&x).map_err(|_| anyhow!("`{}` must be a list", name)))
+            .transpose()
+    }
+
+    pub fn flag(&self, name: &str) -> Result<bool> {
+        Ok(self.get::<bool>(name)?.unwrap_or(false))
+    }
+}
+
+// How matches are picked and paired up, shared by every join: the best `k`
+// per left row, exact-match blocks, and one-to-one assignment
+pub struct MatchOptions {
+    pub top_k: Option<TopK>,
+    pub block_by: Option<List>,
+    pub assignment: Option<Assignment>,
+}
+
+impl MatchOptions {
+    pub fn new(options: &Options) -> Result<Self> {
+        let ties = options.get::<String>("ties")?;
+        Ok(MatchOptions {
+            top_k: TopK::new(options.get("k")?, ties.as_deref().unwrap_or("all"))?,
+            block_by: options.list("block_by")?,
+            assignment: Assignment::new(options.get::<String>("assign")?.as_deref())?,
+        })
+    }
+}
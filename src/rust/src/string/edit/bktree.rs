@@ -0,0 +1,105 @@
+// A Burkhard-Keller tree over the distinct right-hand keys of an edit
+// distance join. Every child edge is labelled with the distance between the
+// child and its parent, so the triangle inequality lets a search skip any
+// subtree whose edge label falls outside `[d - k, d + k]`, where `d` is the
+// distance from the query to the parent and `k` is the threshold. This only
+// holds for true metrics (Levenshtein, Damerau-Levenshtein, Hamming).
+
+use extendr_api::prelude::*;
+
+struct BKNode<'a> {
+    key: &'a str,
+    children: Vec<(usize, usize)>,
+}
+
+pub struct BKTree<'a> {
+    nodes: Vec<BKNode<'a>>,
+    metric: fn(&str, &str) -> usize,
+}
+
+impl<'a> BKTree<'a> {
+    pub fn new<I>(keys: I, metric: fn(&str, &str) -> usize) -> Self
+    where
+        I: IntoIterator<Item = &'a str>,
+    {
+        let mut tree = BKTree {
+            nodes: Vec::new(),
+            metric,
+        };
+        for key in keys {
+            // NA never matches anything, so keep it out of the tree
+            if key.is_na() {
+                continue;
+            }
+            tree.insert(key);
+        }
+        tree
+    }
+
+    fn insert(&mut self, key: &'a str) {
+        let new_idx = self.nodes.len();
+        if new_idx == 0 {
+            self.nodes.push(BKNode {
+                key,
+                children: Vec::new(),
+            });
+            return;
+        }
+
+        let mut current = 0;
+        loop {
+            let dist = (self.metric)(self.nodes[current].key, key);
+
+            // Keys are distinct, but guard against duplicates anyway
+            if dist == 0 {
+                return;
+            }
+
+            let next = self.nodes[current]
+                .children
+                .iter()
+                .find(|(edge, _)| *edge == dist)
+                .map(|(_, child)| *child);
+
+            match next {
+                Some(child) => current = child,
+                None => {
+                    self.nodes[current].children.push((dist, new_idx));
+                    self.nodes.push(BKNode {
+                        key,
+                        children: Vec::new(),
+                    });
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Return every key within `max_distance` of the query along with its
+    /// distance under the tree's metric.
+    pub fn find(&self, query: &str, max_distance: usize) -> Vec<(&'a str, usize)> {
+        let mut out = Vec::new();
+        if self.nodes.is_empty() {
+            return out;
+        }
+
+        let mut stack = vec![0];
+        while let Some(idx) = stack.pop() {
+            let node = &self.nodes[idx];
+            let d = (self.metric)(query, node.key);
+            if d <= max_distance {
+                out.push((node.key, d));
+            }
+
+            let lower = d.saturating_sub(max_distance);
+            let upper = d.saturating_add(max_distance);
+            for &(edge, child) in &node.children {
+                if edge >= lower && edge <= upper {
+                    stack.push(child);
+                }
+            }
+        }
+
+        out
+    }
+}
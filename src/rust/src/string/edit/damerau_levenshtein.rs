@@ -1,12 +1,26 @@
-use crate::string::edit::EditDistance;
+use crate::dedupe::Pairing;
+use crate::string::edit::{length_band, CappedDistance, EditDistance};
 use extendr_api::prelude::*;
-use itertools::iproduct;
 use rapidfuzz::distance::damerau_levenshtein as dl_rf;
 use rayon::prelude::*;
 use rustc_hash::FxHashMap;
 
 pub struct DamerauLevenshtein;
 impl EditDistance for DamerauLevenshtein {
+    fn deletion_distance(&self) -> Option<CappedDistance> {
+        Some(|s1, s2, k| {
+            dl_rf::distance_with_args(
+                s1.chars(),
+                s2.chars(),
+                &dl_rf::Args::default().score_cutoff(k),
+            )
+        })
+    }
+
+    fn metric(&self) -> Option<fn(&str, &str) -> usize> {
+        Some(|s1, s2| dl_rf::distance(s1.chars(), s2.chars()))
+    }
+
     fn compare_pairs(
         &self,
         left: &Vec<&str>,
@@ -48,6 +62,7 @@ impl EditDistance for DamerauLevenshtein {
         length_map: &FxHashMap<usize, Vec<&str>>,
         idx_map: &FxHashMap<&str, Vec<usize>>,
         max_distance: &f64,
+        pairing: Pairing,
     ) -> Option<Vec<(usize, usize, f64)>> {
         // Skip all comparisons if string is NA
         if k1.is_na() {
@@ -57,16 +72,11 @@ impl EditDistance for DamerauLevenshtein {
         let scorer = dl_rf::BatchComparator::new(k1.chars());
         let args = dl_rf::Args::default().score_cutoff(*max_distance as usize);
 
-        // Get range of lengths within max distance of current
-        let k1_len = k1.len();
-        let start_len = k1_len.saturating_sub(*max_distance as usize);
-        let end_len = k1_len.saturating_add(*max_distance as usize + 1);
-
         // Start a list to collect results
         let mut idxs: Vec<(usize, usize, f64)> = Vec::new();
 
         // Begin making string comparisons
-        for i in start_len..end_len {
+        for i in length_band(length_map, k1.len(), *max_distance) {
             if let Some(lookup) = length_map.get(&i) {
                 lookup.iter().for_each(|k2| {
                     // Skip this iter if RHS is NA
@@ -74,12 +84,15 @@ impl EditDistance for DamerauLevenshtein {
                         return;
                     }
 
+                    // A self-join compares each pair of keys once
+                    if !pairing.compares_keys(k1, k2) {
+                        return;
+                    }
+
                     // No need to run distance functions if exactly the same
                     if &k1 == k2 {
                         let v2 = idx_map.get(k2).unwrap();
-                        iproduct!(v1, v2).for_each(|(v1, v2)| {
-                            idxs.push((*v1, *v2, 0.));
-                        });
+                        idxs.extend(pairing.key_rows(v1, v2, 0.));
                         return;
                     }
 
@@ -92,9 +105,7 @@ impl EditDistance for DamerauLevenshtein {
                             // Check vs. threshold
                             if x <= *max_distance {
                                 let v2 = idx_map.get(k2).unwrap();
-                                iproduct!(v1, v2).for_each(|(v1, v2)| {
-                                    idxs.push((*v1, *v2, x as f64));
-                                });
+                                idxs.extend(pairing.key_rows(v1, v2, x));
                                 return;
                             }
                         }
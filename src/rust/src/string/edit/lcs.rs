@@ -1,6 +1,6 @@
-use crate::string::edit::EditDistance;
+use crate::dedupe::Pairing;
+use crate::string::edit::{length_band, EditDistance};
 use extendr_api::prelude::*;
-use itertools::iproduct;
 use rayon::prelude::*;
 use rustc_hash::FxHashMap;
 
@@ -61,22 +61,18 @@ impl EditDistance for LCSStr {
         length_map: &FxHashMap<usize, Vec<&str>>,
         idx_map: &FxHashMap<&str, Vec<usize>>,
         max_distance: &f64,
+        pairing: Pairing,
     ) -> Option<Vec<(usize, usize, f64)>> {
         // Skip all comparisons if string is NA
         if k1.is_na() {
             return None;
         }
 
-        // Get range of lengths within max distance of current
-        let k1_len = k1.len();
-        let start_len = k1_len.saturating_sub(*max_distance as usize);
-        let end_len = k1_len.saturating_add(*max_distance as usize + 1);
-
         // Start a list to collect results
         let mut idxs: Vec<(usize, usize, f64)> = Vec::new();
 
         // Begin making string comparisons
-        for i in start_len..end_len {
+        for i in length_band(length_map, k1.len(), *max_distance) {
             if let Some(lookup) = length_map.get(&i) {
                 lookup.iter().for_each(|k2| {
                     // Skip this iter if RHS is NA
@@ -84,12 +80,15 @@ impl EditDistance for LCSStr {
                         return;
                     }
 
+                    // A self-join compares each pair of keys once
+                    if !pairing.compares_keys(k1, k2) {
+                        return;
+                    }
+
                     // No need to run distance functions if exactly the same
                     if &k1 == k2 {
                         let v2 = idx_map.get(k2).unwrap();
-                        iproduct!(v1, v2).for_each(|(v1, v2)| {
-                            idxs.push((*v1, *v2, 0.));
-                        });
+                        idxs.extend(pairing.key_rows(v1, v2, 0.));
                         return;
                     }
 
@@ -99,9 +98,7 @@ impl EditDistance for LCSStr {
                     // Check vs. threshold
                     if dist <= *max_distance {
                         let v2 = idx_map.get(k2).unwrap();
-                        iproduct!(v1, v2).for_each(|(v1, v2)| {
-                            idxs.push((*v1, *v2, dist));
-                        });
+                        idxs.extend(pairing.key_rows(v1, v2, dist));
                         return;
                     }
                 });
@@ -1,14 +1,52 @@
-use crate::utils::robj_index_map;
+use crate::dedupe::Pairing;
+use crate::string::edit::bktree::BKTree;
+use crate::string::edit::symspell::DeletionIndex;
+use crate::topk::{keep_best, Selection};
+use crate::utils::{robj_index_map, KeyColumn};
 use extendr_api::prelude::*;
 use rayon::iter::*;
 use rayon::ThreadPool;
 use rustc_hash::FxHashMap;
+use std::ops::Range;
 
+pub mod bktree;
 pub mod damerau_levenshtein;
 pub mod hamming;
 pub mod lcs;
 pub mod levenshtein;
 pub mod osa;
+pub mod symspell;
+
+// Below this many distinct right-hand keys, scanning the length band is
+// cheaper than building a BK-tree.
+const BKTREE_MIN_KEYS: usize = 256;
+
+// Lengths of the right-hand keys that can be within `max_distance` of a key
+// of length `len`. The band stops at the longest key, so that best-match
+// joins without a threshold (an infinite `max_distance`) stay finite.
+pub fn length_band(
+    length_map: &FxHashMap<usize, Vec<&str>>,
+    len: usize,
+    max_distance: f64,
+) -> Range<usize> {
+    let longest = length_map.keys().max().copied().unwrap_or(0);
+    let reach = max_distance as usize;
+    len.saturating_sub(reach)..len.saturating_add(reach).min(longest) + 1
+}
+
+// Edit distance between two keys, or `None` when it exceeds the cap
+pub type CappedDistance = fn(&str, &str, usize) -> Option<usize>;
+
+// Candidate lookup structure built once over the distinct right-hand keys
+pub enum CandidateIndex<'a> {
+    // Keys grouped by length; every key in the allowed length band is compared
+    Length(FxHashMap<usize, Vec<&'a str>>),
+    // Metric tree; subtrees are pruned with the triangle inequality
+    BKTree(BKTree<'a>),
+    // Deletion variants; candidates are found by hash lookups and checked
+    // with the scorer's capped distance
+    Deletion(DeletionIndex<'a>, CappedDistance),
+}
 
 // Define a trait for string distance calculations
 pub trait EditDistance: Send + Sync {
@@ -20,29 +58,85 @@ pub trait EditDistance: Send + Sync {
         pool: &rayon::ThreadPool,
     ) -> (Vec<usize>, Vec<f64>);
 
+    // Exact distance for measures that satisfy the triangle inequality.
+    // Returning `Some` allows `fuzzy_indices` to index the right-hand keys
+    // with a BK-tree; non-metrics keep the default and use the length band.
+    fn metric(&self) -> Option<fn(&str, &str) -> usize> {
+        None
+    }
+
+    // Distance capped at `k` (`None` above it) for measures where a distance
+    // of `k` implies that both strings reduce to a common string after at most
+    // `k` deletions each. Returning `Some` makes the symmetric deletion index a
+    // valid candidate filter.
+    fn deletion_distance(&self) -> Option<CappedDistance> {
+        None
+    }
+
+    // Whether a key found in the BK-tree is a match. Scorers whose tree
+    // metric is looser than their own distance filter the hits here.
+    fn tree_match(&self, _k1: &str, _k2: &str) -> bool {
+        true
+    }
+
     fn fuzzy_indices(
         &self,
-        left: &List,
-        left_key: &str,
-        right: &List,
-        right_key: &str,
+        left: KeyColumn,
+        right: KeyColumn,
         max_distance: f64,
+        select: Selection,
         pool: &ThreadPool,
     ) -> anyhow::Result<Vec<(usize, usize, f64)>> {
-        let map1 = robj_index_map(left, left_key)?;
-        let map2 = robj_index_map(right, right_key)?;
+        let map1 = robj_index_map(left.df, left.key)?;
+        let map2 = robj_index_map(right.df, right.key)?;
 
-        let mut length_map: FxHashMap<usize, Vec<&str>> = FxHashMap::default();
-        for key in map2.keys() {
-            let key_len = key.len();
-            length_map.entry(key_len).or_default().push(key);
-        }
+        let deletion = self
+            .deletion_distance()
+            .filter(|_| DeletionIndex::is_suitable(map2.keys().copied(), max_distance));
+
+        let index = match (deletion, self.metric()) {
+            (Some(distance), _) => CandidateIndex::Deletion(
+                DeletionIndex::new(map2.keys().copied(), max_distance),
+                distance,
+            ),
+            (None, Some(metric)) if map2.len() >= BKTREE_MIN_KEYS => {
+                CandidateIndex::BKTree(BKTree::new(map2.keys().copied(), metric))
+            }
+            _ => {
+                let mut length_map: FxHashMap<usize, Vec<&str>> = FxHashMap::default();
+                for key in map2.keys() {
+                    let key_len = key.len();
+                    length_map.entry(key_len).or_default().push(key);
+                }
+                CandidateIndex::Length(length_map)
+            }
+        };
 
         let idxs: Vec<(usize, usize, f64)> = pool.install(|| {
             map1.par_iter()
-                .filter_map(|(k1, v1)| {
-                    self.compare_one_to_many(k1, v1, &length_map, &map2, &max_distance)
+                .filter_map(|(k1, v1)| match &index {
+                    CandidateIndex::BKTree(tree) => {
+                        self.tree_matches(k1, v1, tree, &map2, max_distance, select.pairing)
+                    }
+                    CandidateIndex::Deletion(deletions, distance) => deletion_matches(
+                        k1,
+                        v1,
+                        deletions,
+                        *distance,
+                        &map2,
+                        max_distance,
+                        select.pairing,
+                    ),
+                    CandidateIndex::Length(length_map) => self.compare_one_to_many(
+                        k1,
+                        v1,
+                        length_map,
+                        &map2,
+                        &max_distance,
+                        select.pairing,
+                    ),
                 })
+                .map(|idxs| keep_best(idxs, select.top_k))
                 .flatten()
                 .collect()
         });
@@ -50,6 +144,37 @@ pub trait EditDistance: Send + Sync {
         Ok(idxs)
     }
 
+    // Matches of one left key among the keys held in a BK-tree
+    fn tree_matches(
+        &self,
+        k1: &str,
+        v1: &[usize],
+        tree: &BKTree,
+        idx_map: &FxHashMap<&str, Vec<usize>>,
+        max_distance: f64,
+        pairing: Pairing,
+    ) -> Option<Vec<(usize, usize, f64)>> {
+        if k1.is_na() {
+            return None;
+        }
+
+        let idxs: Vec<(usize, usize, f64)> = tree
+            .find(k1, max_distance as usize)
+            .into_iter()
+            .filter(|(k2, _)| pairing.compares_keys(k1, k2) && self.tree_match(k1, k2))
+            .flat_map(|(k2, dist)| {
+                let v2 = idx_map.get(k2).unwrap();
+                pairing.key_rows(v1, v2, dist as f64)
+            })
+            .collect();
+        if idxs.is_empty() {
+            None
+        } else {
+            Some(idxs)
+        }
+    }
+
+    // Matches of one left key among the keys in its length band
     fn compare_one_to_many(
         &self,
         k1: &str,
@@ -57,5 +182,39 @@ pub trait EditDistance: Send + Sync {
         length_map: &FxHashMap<usize, Vec<&str>>,
         idx_map: &FxHashMap<&str, Vec<usize>>,
         max_distance: &f64,
+        pairing: Pairing,
     ) -> Option<Vec<(usize, usize, f64)>>;
 }
+
+// Matches of one left key among the candidates from the deletion index
+fn deletion_matches(
+    k1: &str,
+    v1: &[usize],
+    deletions: &DeletionIndex,
+    distance: CappedDistance,
+    idx_map: &FxHashMap<&str, Vec<usize>>,
+    max_distance: f64,
+    pairing: Pairing,
+) -> Option<Vec<(usize, usize, f64)>> {
+    if k1.is_na() {
+        return None;
+    }
+
+    let cutoff = max_distance as usize;
+    let idxs: Vec<(usize, usize, f64)> = deletions
+        .candidates(k1)
+        .into_iter()
+        .filter(|k2| pairing.compares_keys(k1, k2))
+        .filter_map(|k2| distance(k1, k2, cutoff).map(|dist| (k2, dist as f64)))
+        .filter(|(_, dist)| *dist <= max_distance)
+        .flat_map(|(k2, dist)| {
+            let v2 = idx_map.get(k2).unwrap();
+            pairing.key_rows(v1, v2, dist)
+        })
+        .collect();
+    if idxs.is_empty() {
+        None
+    } else {
+        Some(idxs)
+    }
+}
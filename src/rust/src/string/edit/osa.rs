@@ -1,12 +1,22 @@
-use crate::string::EditDistance;
+use crate::dedupe::Pairing;
+use crate::string::edit::{length_band, CappedDistance, EditDistance};
 use extendr_api::prelude::*;
-use itertools::iproduct;
 use rapidfuzz::distance::osa as osa_rf;
 use rayon::prelude::*;
 use rustc_hash::FxHashMap;
 
 pub struct OSA;
 impl EditDistance for OSA {
+    fn deletion_distance(&self) -> Option<CappedDistance> {
+        Some(|s1, s2, k| {
+            osa_rf::distance_with_args(
+                s1.chars(),
+                s2.chars(),
+                &osa_rf::Args::default().score_cutoff(k),
+            )
+        })
+    }
+
     fn compare_pairs(
         &self,
         left: &Vec<&str>,
@@ -49,6 +59,7 @@ impl EditDistance for OSA {
         length_map: &FxHashMap<usize, Vec<&str>>,
         idx_map: &FxHashMap<&str, Vec<usize>>,
         max_distance: &f64,
+        pairing: Pairing,
     ) -> Option<Vec<(usize, usize, f64)>> {
         // Skip all comparisons if string is NA
         if k1.is_na() {
@@ -58,16 +69,11 @@ impl EditDistance for OSA {
         let scorer = osa_rf::BatchComparator::new(k1.chars());
         let args = osa_rf::Args::default().score_cutoff(*max_distance as usize);
 
-        // Get range of lengths within max distance of current
-        let k1_len = k1.len();
-        let start_len = k1_len.saturating_sub(*max_distance as usize);
-        let end_len = k1_len.saturating_add(*max_distance as usize + 1);
-
         // Start a list to collect results
         let mut idxs: Vec<(usize, usize, f64)> = Vec::new();
 
         // Begin making string comparisons
-        for i in start_len..end_len {
+        for i in length_band(length_map, k1.len(), *max_distance) {
             if let Some(lookup) = length_map.get(&i) {
                 lookup.iter().for_each(|k2| {
                     // Skip this iter if RHS is NA
@@ -75,12 +81,15 @@ impl EditDistance for OSA {
                         return;
                     }
 
+                    // A self-join compares each pair of keys once
+                    if !pairing.compares_keys(k1, k2) {
+                        return;
+                    }
+
                     // No need to run distance functions if exactly the same
                     if &k1 == k2 {
                         let v2 = idx_map.get(k2).unwrap();
-                        iproduct!(v1, v2).for_each(|(v1, v2)| {
-                            idxs.push((*v1, *v2, 0.));
-                        });
+                        idxs.extend(pairing.key_rows(v1, v2, 0.));
                         return;
                     }
 
@@ -93,9 +102,7 @@ impl EditDistance for OSA {
                             // Check vs. threshold
                             if x <= *max_distance {
                                 let v2 = idx_map.get(k2).unwrap();
-                                iproduct!(v1, v2).for_each(|(v1, v2)| {
-                                    idxs.push((*v1, *v2, x as f64));
-                                });
+                                idxs.extend(pairing.key_rows(v1, v2, x));
                                 return;
                             }
                         }
@@ -0,0 +1,103 @@
+// Symmetric deletion index in the style of SymSpell (Garbe). Two strings
+// within edit distance `k` always share a string that can be reached from
+// both by deleting at most `k` characters, so indexing every deletion variant
+// of the right-hand keys turns candidate generation into hash lookups. The
+// candidates are a superset of the true matches and are verified afterwards
+// with the exact scorer.
+
+use extendr_api::prelude::*;
+use rustc_hash::{FxHashMap, FxHashSet};
+
+// Deletion variants grow roughly as `len^k`, so the index only pays off for
+// small thresholds on reasonably long strings.
+const MAX_DELETIONS: usize = 2;
+const MIN_LENGTH_RATIO: f64 = 3.0;
+
+pub struct DeletionIndex<'a> {
+    max_deletions: usize,
+    variants: FxHashMap<String, Vec<&'a str>>,
+}
+
+impl<'a> DeletionIndex<'a> {
+    // Decide whether the index is worth building for these keys
+    pub fn is_suitable<'b, I>(keys: I, max_distance: f64) -> bool
+    where
+        I: IntoIterator<Item = &'b str>,
+    {
+        let k = max_distance as usize;
+        if max_distance < 0.0 || k > MAX_DELETIONS {
+            return false;
+        }
+
+        let (total, count) = keys
+            .into_iter()
+            .filter(|key| !key.is_na())
+            .fold((0, 0), |(total, count), key| {
+                (total + key.chars().count(), count + 1)
+            });
+
+        if count == 0 {
+            return false;
+        }
+
+        let mean_len = total as f64 / count as f64;
+        (k as f64) * MIN_LENGTH_RATIO <= mean_len
+    }
+
+    pub fn new<I>(keys: I, max_distance: f64) -> Self
+    where
+        I: IntoIterator<Item = &'a str>,
+    {
+        let max_deletions = max_distance as usize;
+        let mut variants: FxHashMap<String, Vec<&'a str>> = FxHashMap::default();
+
+        for key in keys {
+            if key.is_na() {
+                continue;
+            }
+            for variant in deletion_variants(key, max_deletions) {
+                variants.entry(variant).or_default().push(key);
+            }
+        }
+
+        DeletionIndex {
+            max_deletions,
+            variants,
+        }
+    }
+
+    // Distinct right-hand keys sharing at least one deletion variant with `key`
+    pub fn candidates(&self, key: &str) -> FxHashSet<&'a str> {
+        let mut out = FxHashSet::default();
+        for variant in deletion_variants(key, self.max_deletions) {
+            if let Some(keys) = self.variants.get(&variant) {
+                out.extend(keys.iter().copied());
+            }
+        }
+        out
+    }
+}
+
+// Every string obtained by deleting up to `max_deletions` characters,
+// including the string itself
+fn deletion_variants(s: &str, max_deletions: usize) -> FxHashSet<String> {
+    let mut out: FxHashSet<String> = FxHashSet::default();
+    out.insert(s.to_string());
+
+    let mut frontier: Vec<Vec<char>> = vec![s.chars().collect()];
+    for _ in 0..max_deletions {
+        let mut next = Vec::new();
+        for chars in &frontier {
+            for i in 0..chars.len() {
+                let mut shorter = chars.clone();
+                shorter.remove(i);
+                if out.insert(shorter.iter().collect()) {
+                    next.push(shorter);
+                }
+            }
+        }
+        frontier = next;
+    }
+
+    out
+}
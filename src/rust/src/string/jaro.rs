@@ -0,0 +1,126 @@
+use crate::dedupe::Pairing;
+use crate::topk::{keep_best, Selection};
+use crate::utils::{robj_index_map, KeyColumn};
+use anyhow::Result;
+use extendr_api::prelude::*;
+use rapidfuzz::distance::jaro as jaro_rf;
+use rayon::prelude::*;
+use rayon::ThreadPool;
+use rustc_hash::FxHashMap;
+
+// Jaro similarity is the mean of m/|a|, m/|b| and (m - t)/m for m matching
+// characters and t transpositions. With m at most the shorter length and no
+// transpositions, two strings whose lengths differ by `gap` are at least
+// gap / (3 * longer) apart, so most lengths can be skipped outright.
+fn within_length_bound(len1: usize, len2: usize, max_distance: f64) -> bool {
+    let gap = len1.abs_diff(len2) as f64;
+    // The slack keeps pairs that sit exactly on the bound
+    gap <= 3.0 * max_distance * len1.max(len2) as f64 + 1e-9
+}
+
+pub struct Jaro;
+impl Jaro {
+    pub fn fuzzy_indices(
+        &self,
+        left: KeyColumn,
+        right: KeyColumn,
+        max_distance: f64,
+        select: Selection,
+        pool: &ThreadPool,
+    ) -> Result<Vec<(usize, usize, f64)>> {
+        let map1 = robj_index_map(left.df, left.key)?;
+        let map2 = robj_index_map(right.df, right.key)?;
+
+        // Right-hand keys grouped by length in characters
+        let mut length_map: FxHashMap<usize, Vec<&str>> = FxHashMap::default();
+        for key in map2.keys() {
+            if !key.is_na() {
+                length_map.entry(key.chars().count()).or_default().push(key);
+            }
+        }
+
+        let idxs: Vec<(usize, usize, f64)> = pool.install(|| {
+            map1.par_iter()
+                .filter_map(|(k1, v1)| {
+                    self.compare_one_to_many(
+                        k1,
+                        v1,
+                        &length_map,
+                        &map2,
+                        max_distance,
+                        select.pairing,
+                    )
+                })
+                .map(|idxs| keep_best(idxs, select.top_k))
+                .flatten()
+                .collect()
+        });
+        Ok(idxs)
+    }
+
+    pub fn compare_pairs(
+        &self,
+        left: &Vec<&str>,
+        right: &Vec<&str>,
+        max_distance: &f64,
+        pool: &rayon::ThreadPool,
+    ) -> (Vec<usize>, Vec<f64>) {
+        let args = jaro_rf::Args::default().score_cutoff(*max_distance);
+        let (keep, dists): (Vec<usize>, Vec<f64>) = pool.install(|| {
+            left.par_iter()
+                .zip(right)
+                .enumerate()
+                .filter_map(|(i, (l, r))| {
+                    if l.is_na() || r.is_na() {
+                        return None;
+                    }
+                    jaro_rf::distance_with_args(l.chars(), r.chars(), &args).map(|x| (i, x))
+                })
+                .unzip()
+        });
+        (keep, dists)
+    }
+
+    fn compare_one_to_many(
+        &self,
+        k1: &str,
+        v1: &[usize],
+        length_map: &FxHashMap<usize, Vec<&str>>,
+        idx_map: &FxHashMap<&str, Vec<usize>>,
+        max_distance: f64,
+        pairing: Pairing,
+    ) -> Option<Vec<(usize, usize, f64)>> {
+        if k1.is_na() {
+            return None;
+        }
+
+        let k1_len = k1.chars().count();
+        let scorer = jaro_rf::BatchComparator::new(k1.chars());
+        let args = jaro_rf::Args::default().score_cutoff(max_distance);
+        let mut idxs: Vec<(usize, usize, f64)> = Vec::new();
+
+        for (&len, keys) in length_map.iter() {
+            if !within_length_bound(k1_len, len, max_distance) {
+                continue;
+            }
+
+            // A self-join compares each pair of keys once
+            for k2 in keys.iter().filter(|k2| pairing.compares_keys(k1, k2)) {
+                let dist = if k1 == *k2 {
+                    Some(0.)
+                } else {
+                    scorer.distance_with_args(k2.chars(), &args)
+                };
+                if let Some(x) = dist {
+                    idxs.extend(pairing.key_rows(v1, &idx_map[k2], x));
+                }
+            }
+        }
+
+        if idxs.is_empty() {
+            None
+        } else {
+            Some(idxs)
+        }
+    }
+}
@@ -1,33 +1,38 @@
-use crate::utils::robj_index_map;
+use crate::dedupe::Pairing;
+use crate::topk::{keep_best, Selection};
+use crate::utils::{robj_index_map, KeyColumn};
 use anyhow::Result;
 use extendr_api::prelude::*;
-use itertools::iproduct;
 use rapidfuzz::distance::jaro as jaro_rf;
 use rayon::prelude::*;
 use rayon::ThreadPool;
 use rustc_hash::FxHashMap;
 
-pub struct JaroWinkler;
+// Jaro distance lowered for a common prefix of up to `max_prefix`
+// characters, each weighted by `prefix_weight`
+pub struct JaroWinkler {
+    pub prefix_weight: f64,
+    pub max_prefix: usize,
+}
+
 impl JaroWinkler {
     pub fn fuzzy_indices(
         &self,
-        df1: &List,
-        left_key: &str,
-        df2: &List,
-        right_key: &str,
+        left: KeyColumn,
+        right: KeyColumn,
         max_distance: f64,
-        prefix_weight: f64,
-        max_prefix: usize,
+        select: Selection,
         pool: &ThreadPool,
     ) -> Result<Vec<(usize, usize, f64)>> {
-        let map1 = robj_index_map(&df1, left_key)?;
-        let map2 = robj_index_map(&df2, right_key)?;
+        let map1 = robj_index_map(left.df, left.key)?;
+        let map2 = robj_index_map(right.df, right.key)?;
 
         let idxs: Vec<(usize, usize, f64)> = pool.install(|| {
             map1.par_iter()
                 .filter_map(|(k1, v1)| {
-                    self.compare_one_to_many(k1, v1, &map2, max_distance, prefix_weight, max_prefix)
+                    self.compare_one_to_many(k1, v1, &map2, max_distance, select.pairing)
                 })
+                .map(|idxs| keep_best(idxs, select.top_k))
                 .flatten()
                 .collect()
         });
@@ -39,10 +44,9 @@ impl JaroWinkler {
         left: &Vec<&str>,
         right: &Vec<&str>,
         max_distance: &f64,
-        prefix_weight: f64,
-        max_prefix: usize,
         pool: &rayon::ThreadPool,
     ) -> (Vec<usize>, Vec<f64>) {
+        let (prefix_weight, max_prefix) = (self.prefix_weight, self.max_prefix);
         let args = jaro_rf::Args::default().score_cutoff(*max_distance);
         let (keep, dists): (Vec<usize>, Vec<f64>) = pool.install(|| {
             left.par_iter()
@@ -83,27 +87,26 @@ impl JaroWinkler {
     fn compare_one_to_many(
         &self,
         k1: &str,
-        v1: &Vec<usize>,
+        v1: &[usize],
         idx_map: &FxHashMap<&str, Vec<usize>>,
         max_distance: f64,
-        prefix_weight: f64,
-        max_prefix: usize,
+        pairing: Pairing,
     ) -> Option<Vec<(usize, usize, f64)>> {
         if k1.is_na() {
             return None;
         }
+        let (prefix_weight, max_prefix) = (self.prefix_weight, self.max_prefix);
 
         let mut idxs: Vec<(usize, usize, f64)> = Vec::new();
 
         for (k2, v2) in idx_map.iter() {
-            if k2.is_na() {
+            // A self-join compares each pair of keys once
+            if k2.is_na() || !pairing.compares_keys(k1, k2) {
                 continue;
             }
 
             if &k1 == k2 {
-                iproduct!(v1, v2).for_each(|(v1, v2)| {
-                    idxs.push((*v1, *v2, 0.));
-                });
+                idxs.extend(pairing.key_rows(v1, v2, 0.));
                 continue;
             }
 
@@ -123,9 +126,7 @@ impl JaroWinkler {
                 Some(x) => {
                     let x2 = x + (capped_prefix_len as f64 * prefix_weight * (1.0 - x)) as f64;
                     if x2 <= max_distance {
-                        iproduct!(v1, v2).for_each(|(a, b)| {
-                            idxs.push((*a, *b, x2));
-                        });
+                        idxs.extend(pairing.key_rows(v1, v2, x2));
                     }
                 }
                 None => (),
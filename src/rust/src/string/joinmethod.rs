@@ -1,3 +1,8 @@
+use crate::string::ngram::minhash::MinHashLsh;
+use crate::topk::Selection;
+use crate::utils::KeyColumn;
+use extendr_api::prelude::*;
+
 pub enum JoinMethod {
     OSA {
         max_distance: f64,
@@ -26,12 +31,27 @@ pub enum JoinMethod {
         max_distance: f64,
         q: usize,
     },
+    JaccardLsh {
+        max_distance: f64,
+        q: usize,
+        lsh: MinHashLsh,
+    },
+    Jaro {
+        max_distance: f64,
+    },
     JaroWinkler {
         max_distance: f64,
         prefix_weight: f64,
         max_prefix: usize,
     },
     Soundex {},
+    Metaphone {},
+    DoubleMetaphone {},
+    Nysiis {},
+    Caverphone {},
+    MatchRating {},
+    Cologne {},
+    DaitchMokotoff {},
 }
 
 impl JoinMethod {
@@ -41,57 +61,104 @@ impl JoinMethod {
         left_key: &str,
         right: &extendr_api::List,
         right_key: &str,
+        select: Selection,
         pool: &rayon::ThreadPool,
     ) -> anyhow::Result<Vec<(usize, usize, f64)>> {
         use crate::string::*;
 
-        let result =
-            match self {
-                JoinMethod::OSA { max_distance } => {
-                    OSA.fuzzy_indices(left, left_key, right, right_key, *max_distance, pool)
-                }
-                JoinMethod::Levenshtein { max_distance } => {
-                    Levenshtein.fuzzy_indices(left, left_key, right, right_key, *max_distance, pool)
-                }
-                JoinMethod::DamerauLevenshtein { max_distance } => DamerauLevenshtein
-                    .fuzzy_indices(left, left_key, right, right_key, *max_distance, pool),
-                JoinMethod::Hamming { max_distance } => {
-                    Hamming.fuzzy_indices(left, left_key, right, right_key, *max_distance, pool)
-                }
-                JoinMethod::LCS { max_distance } => {
-                    LCSStr.fuzzy_indices(left, left_key, right, right_key, *max_distance, pool)
-                }
-                JoinMethod::QGram { max_distance, q } => {
-                    QGram.fuzzy_indices(left, left_key, right, right_key, *max_distance, *q, pool)
-                }
-                JoinMethod::Cosine { max_distance, q } => {
-                    Cosine.fuzzy_indices(left, left_key, right, right_key, *max_distance, *q, pool)
-                }
-                JoinMethod::Jaccard { max_distance, q } => {
-                    Jaccard.fuzzy_indices(left, left_key, right, right_key, *max_distance, *q, pool)
-                }
-                JoinMethod::JaroWinkler {
-                    max_distance,
-                    prefix_weight,
-                    max_prefix,
-                } => JaroWinkler.fuzzy_indices(
-                    left,
-                    left_key,
-                    right,
-                    right_key,
-                    *max_distance,
-                    *prefix_weight,
-                    *max_prefix,
-                    pool,
-                ),
-                JoinMethod::Soundex {} => {
-                    Soundex.fuzzy_indices(left, left_key, right, right_key, pool)
-                }
-            }?;
+        let left = KeyColumn {
+            df: left,
+            key: left_key,
+        };
+        let right = KeyColumn {
+            df: right,
+            key: right_key,
+        };
+        let result = match self {
+            JoinMethod::OSA { max_distance } => {
+                OSA.fuzzy_indices(left, right, *max_distance, select, pool)
+            }
+            JoinMethod::Levenshtein { max_distance } => {
+                Levenshtein.fuzzy_indices(left, right, *max_distance, select, pool)
+            }
+            JoinMethod::DamerauLevenshtein { max_distance } => {
+                DamerauLevenshtein.fuzzy_indices(left, right, *max_distance, select, pool)
+            }
+            JoinMethod::Hamming { max_distance } => {
+                Hamming.fuzzy_indices(left, right, *max_distance, select, pool)
+            }
+            JoinMethod::LCS { max_distance } => {
+                LCSStr.fuzzy_indices(left, right, *max_distance, select, pool)
+            }
+            JoinMethod::QGram { max_distance, q } => {
+                QGram.fuzzy_indices(left, right, *max_distance, *q, select, pool)
+            }
+            JoinMethod::Cosine { max_distance, q } => {
+                Cosine.fuzzy_indices(left, right, *max_distance, *q, select, pool)
+            }
+            JoinMethod::Jaccard { max_distance, q } => {
+                Jaccard.fuzzy_indices(left, right, *max_distance, *q, select, pool)
+            }
+            JoinMethod::JaccardLsh {
+                max_distance,
+                q,
+                lsh,
+            } => lsh.fuzzy_indices(left, right, *max_distance, *q, select, pool),
+            JoinMethod::Jaro { max_distance } => {
+                Jaro.fuzzy_indices(left, right, *max_distance, select, pool)
+            }
+            JoinMethod::JaroWinkler {
+                max_distance,
+                prefix_weight,
+                max_prefix,
+            } => JaroWinkler {
+                prefix_weight: *prefix_weight,
+                max_prefix: *max_prefix,
+            }
+            .fuzzy_indices(left, right, *max_distance, select, pool),
+            JoinMethod::Soundex {} => Soundex.fuzzy_indices(left, right, select, pool),
+            JoinMethod::Metaphone {} => Metaphone.fuzzy_indices(left, right, select, pool),
+            JoinMethod::DoubleMetaphone {} => {
+                DoubleMetaphone.fuzzy_indices(left, right, select, pool)
+            }
+            JoinMethod::Nysiis {} => Nysiis.fuzzy_indices(left, right, select, pool),
+            JoinMethod::Caverphone {} => Caverphone.fuzzy_indices(left, right, select, pool),
+            JoinMethod::MatchRating {} => MatchRating.fuzzy_indices(left, right, select, pool),
+            JoinMethod::Cologne {} => Cologne.fuzzy_indices(left, right, select, pool),
+            JoinMethod::DaitchMokotoff {} => {
+                DaitchMokotoff.fuzzy_indices(left, right, select, pool)
+            }
+        }?;
 
         Ok(result)
     }
 
+    // Threshold of this method. Phonetic codes either match or they don't,
+    // which behaves like a threshold of zero.
+    pub fn max_distance(&self) -> f64 {
+        match self {
+            JoinMethod::OSA { max_distance }
+            | JoinMethod::Levenshtein { max_distance }
+            | JoinMethod::DamerauLevenshtein { max_distance }
+            | JoinMethod::Hamming { max_distance }
+            | JoinMethod::LCS { max_distance }
+            | JoinMethod::QGram { max_distance, .. }
+            | JoinMethod::Cosine { max_distance, .. }
+            | JoinMethod::Jaccard { max_distance, .. }
+            | JoinMethod::JaccardLsh { max_distance, .. }
+            | JoinMethod::Jaro { max_distance }
+            | JoinMethod::JaroWinkler { max_distance, .. } => *max_distance,
+            JoinMethod::Soundex {}
+            | JoinMethod::Metaphone {}
+            | JoinMethod::DoubleMetaphone {}
+            | JoinMethod::Nysiis {}
+            | JoinMethod::Caverphone {}
+            | JoinMethod::MatchRating {}
+            | JoinMethod::Cologne {}
+            | JoinMethod::DaitchMokotoff {} => 0.0,
+        }
+    }
+
     pub fn compare_pairs(
         &self,
         left: &Vec<&str>,
@@ -125,19 +192,30 @@ impl JoinMethod {
             JoinMethod::Jaccard { max_distance, q } => {
                 Ok(Jaccard.compare_pairs(left, right, q, max_distance, pool))
             }
+            // Pairs are already known, so there is nothing to gain from LSH
+            JoinMethod::JaccardLsh {
+                max_distance, q, ..
+            } => Ok(Jaccard.compare_pairs(left, right, q, max_distance, pool)),
+            JoinMethod::Jaro { max_distance } => {
+                Ok(Jaro.compare_pairs(left, right, max_distance, pool))
+            }
             JoinMethod::JaroWinkler {
                 max_distance,
                 prefix_weight,
                 max_prefix,
-            } => Ok(JaroWinkler.compare_pairs(
-                left,
-                right,
-                max_distance,
-                *prefix_weight,
-                *max_prefix,
-                pool,
-            )),
+            } => Ok(JaroWinkler {
+                prefix_weight: *prefix_weight,
+                max_prefix: *max_prefix,
+            }
+            .compare_pairs(left, right, max_distance, pool)),
             JoinMethod::Soundex {} => Soundex.compare_pairs(left, right, pool),
+            JoinMethod::Metaphone {} => Metaphone.compare_pairs(left, right, pool),
+            JoinMethod::DoubleMetaphone {} => DoubleMetaphone.compare_pairs(left, right, pool),
+            JoinMethod::Nysiis {} => Nysiis.compare_pairs(left, right, pool),
+            JoinMethod::Caverphone {} => Caverphone.compare_pairs(left, right, pool),
+            JoinMethod::MatchRating {} => MatchRating.compare_pairs(left, right, pool),
+            JoinMethod::Cologne {} => Cologne.compare_pairs(left, right, pool),
+            JoinMethod::DaitchMokotoff {} => DaitchMokotoff.compare_pairs(left, right, pool),
         };
 
         result
@@ -150,7 +228,15 @@ pub fn get_join_method(
     q: Option<usize>,
     prefix_weight: Option<f64>,
     max_prefix: Option<usize>,
+    lsh: Option<MinHashLsh>,
 ) -> anyhow::Result<JoinMethod> {
+    if lsh.is_some() && method != "jaccard" {
+        return Err(anyhow::anyhow!(
+            "`lsh` is only supported for method `jaccard`, not `{}`",
+            method
+        ));
+    }
+
     match method {
         "osa" => Ok(JoinMethod::OSA { max_distance }),
         "levenshtein" | "lv" => Ok(JoinMethod::Levenshtein { max_distance }),
@@ -165,10 +251,18 @@ pub fn get_join_method(
             max_distance,
             q: q.ok_or_else(|| anyhow::anyhow!("Must provide `q` for method `cosine`"))?,
         }),
-        "jaccard" => Ok(JoinMethod::Jaccard {
-            max_distance,
-            q: q.ok_or_else(|| anyhow::anyhow!("Must provide `q` for method `jaccard`"))?,
-        }),
+        "jaccard" => {
+            let q = q.ok_or_else(|| anyhow::anyhow!("Must provide `q` for method `jaccard`"))?;
+            match lsh {
+                Some(lsh) => Ok(JoinMethod::JaccardLsh {
+                    max_distance,
+                    q,
+                    lsh,
+                }),
+                None => Ok(JoinMethod::Jaccard { max_distance, q }),
+            }
+        }
+        "jaro" => Ok(JoinMethod::Jaro { max_distance }),
         "jaro_winkler" | "jw" => Ok(JoinMethod::JaroWinkler {
             max_distance,
             prefix_weight: prefix_weight
@@ -176,7 +270,45 @@ pub fn get_join_method(
             max_prefix: max_prefix.ok_or_else(|| anyhow::anyhow!("Must provide `max_prefix`"))?,
         }),
         "soundex" => Ok(JoinMethod::Soundex {}),
+        "metaphone" => Ok(JoinMethod::Metaphone {}),
+        "double_metaphone" | "dmetaphone" => Ok(JoinMethod::DoubleMetaphone {}),
+        "nysiis" => Ok(JoinMethod::Nysiis {}),
+        "caverphone" => Ok(JoinMethod::Caverphone {}),
+        "mra" => Ok(JoinMethod::MatchRating {}),
+        "cologne" => Ok(JoinMethod::Cologne {}),
+        "daitch_mokotoff" | "dm_soundex" => Ok(JoinMethod::DaitchMokotoff {}),
 
         _ => Err(anyhow::anyhow!("Unsupported method `{}`", method)),
     }
 }
+
+// Build the method for one join column from a spec: a named list with
+// `method` and `max_distance`, plus `q`, `max_prefix` and `prefix_weight`
+// where the method needs them
+pub fn method_from_spec(spec: &Robj, lsh: Option<MinHashLsh>) -> anyhow::Result<JoinMethod> {
+    let spec =
+        List::try_from(spec).map_err(|_| anyhow::anyhow!("Each method spec must be a list"))?;
+    let field = |name: &str| spec.dollar(name).ok().filter(|x| !x.is_null());
+    let number = |name: &str| {
+        field(name).and_then(|x| {
+            x.as_real()
+                .or_else(|| x.as_integer().map(|i| i as f64))
+                .filter(|v| !v.is_nan())
+        })
+    };
+
+    let method = field("method")
+        .and_then(|x| x.as_str().map(str::to_string))
+        .ok_or_else(|| anyhow::anyhow!("Method spec is missing `method`"))?;
+    let max_distance = number("max_distance")
+        .ok_or_else(|| anyhow::anyhow!("Method spec for `{}` is missing `max_distance`", method))?;
+
+    get_join_method(
+        &method,
+        max_distance,
+        number("q").map(|x| x as usize),
+        number("prefix_weight"),
+        number("max_prefix").map(|x| x as usize),
+        lsh,
+    )
+}
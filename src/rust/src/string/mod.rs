@@ -1,24 +1,37 @@
 pub mod edit;
+pub mod jaro;
 pub mod jaro_winkler;
 pub mod joinmethod;
 pub mod ngram;
-pub mod soundex;
+pub mod phonetic;
 
+use crate::assign::assign_matches;
+use crate::block::{blocked_matches, Matches};
+use crate::composite::CompositeScore;
+use crate::dedupe::{cluster_ids, Pairing};
 use crate::merge::{dispatch_join, DistanceData};
+use crate::options::{MatchOptions, Options};
 use crate::string::edit::{
     damerau_levenshtein::DamerauLevenshtein, hamming::Hamming, lcs::LCSStr,
     levenshtein::Levenshtein, osa::OSA, EditDistance,
 };
+use crate::string::jaro::Jaro;
 use crate::string::jaro_winkler::JaroWinkler;
-use crate::string::joinmethod::get_join_method;
+use crate::string::joinmethod::{get_join_method, method_from_spec, JoinMethod};
+use crate::string::ngram::minhash::MinHashLsh;
 use crate::string::ngram::{cosine::Cosine, jaccard::Jaccard, qgram::QGram, QGramDistance};
-use crate::string::soundex::Soundex;
-use crate::utils::get_pool;
+use crate::string::phonetic::{
+    caverphone::Caverphone, cologne::Cologne, daitch_mokotoff::DaitchMokotoff,
+    double_metaphone::DoubleMetaphone, metaphone::Metaphone, mra::MatchRating, nysiis::Nysiis,
+    soundex::Soundex, PhoneticEncoder,
+};
+use crate::topk::{keep_best_rows, Selection, TopK};
+use crate::utils::{df_nrows, get_pool, subset_pairs};
 
 use anyhow::{anyhow, Result};
 use extendr_api::prelude::*;
 
-fn extract_keys(by: &List) -> Result<Vec<(String, String)>> {
+pub fn extract_keys(by: &List) -> Result<Vec<(String, String)>> {
     by.iter()
         .map(|(left_key, val)| {
             let right_keys = val
@@ -38,94 +51,287 @@ pub fn string_join(
     df1: List,
     df2: List,
     by: List,
-    method: String,
     how: String,
-    max_distance: f64,
     distance_col: Option<String>,
-    q: Option<i32>,
-    max_prefix: Option<i32>,
-    prefix_weight: Option<f64>,
+    options: Options,
     nthread: Option<usize>,
 ) -> Result<List> {
-    let keys: Vec<(String, String)> = extract_keys(&by)?;
     let pool = get_pool(nthread)?;
-    let (left_key, right_key) = &keys[0];
+    let matching = MatchOptions::new(&options)?;
+    let spec = StringSpec::new(&options, &by, matching.top_k)?;
+    let matches = spec.pairs(
+        &df1,
+        &df2,
+        matching.block_by.as_ref(),
+        Pairing::Cross,
+        &pool,
+    )?;
 
-    let qz = match q {
-        Some(x) => Some(x as usize),
-        None => None,
-    };
+    // A composite score comes last, after one distance per key column, and
+    // ranks pairs when there is one
+    let scored = matches.2.len() > by.len();
+    let cost_col = if scored { by.len() } else { 0 };
+    let (idxs1, idxs2, mut dists) = assign_matches(matches, cost_col, matching.assignment)?;
 
-    let max_prefix = match max_prefix {
-        Some(x) => Some(x as usize),
-        None => None,
+    let scores;
+    let dists = if scored {
+        scores = dists.pop().unwrap_or_default();
+        DistanceData::Scored(&dists, &scores)
+    } else if by.len() == 1 {
+        DistanceData::Single(&dists[0])
+    } else {
+        DistanceData::Matrix(&dists)
     };
 
-    let prefix_weight = match prefix_weight {
-        Some(x) => Some(x as f64),
-        None => None,
-    };
+    let out = dispatch_join(
+        how.as_str(),
+        &df1,
+        &df2,
+        idxs1,
+        idxs2,
+        distance_col,
+        dists,
+        by,
+    );
+    Ok(out)
+}
+
+// Cluster ID of every row of `df`, from a string self-join
+pub fn string_dedupe(
+    df: List,
+    by: List,
+    options: Options,
+    nthread: Option<usize>,
+) -> Result<Vec<i32>> {
+    let pool = get_pool(nthread)?;
+    let matching = MatchOptions::new(&options)?;
+    let spec = StringSpec::new(&options, &by, None)?;
+    let (idxs1, idxs2, _) = spec.pairs(
+        &df,
+        &df,
+        matching.block_by.as_ref(),
+        Pairing::SelfJoin,
+        &pool,
+    )?;
+    Ok(cluster_ids(df_nrows(&df), &idxs1, &idxs2))
+}
+
+// What a string join matches on: one method per key column, plus the
+// composite score and best-match selection when they are set. It is read
+// once and reused for every block.
+pub struct StringSpec {
+    keys: Vec<(String, String)>,
+    join_methods: Vec<JoinMethod>,
+    composite: Option<CompositeScore>,
+    top_k: Option<TopK>,
+}
+
+impl StringSpec {
+    pub fn new(options: &Options, by: &List, top_k: Option<TopK>) -> Result<Self> {
+        let keys: Vec<(String, String)> = extract_keys(by)?;
 
-    let join_method = get_join_method(&method, max_distance, qz, prefix_weight, max_prefix)?;
-    let mut matchdat = join_method.fuzzy_indices(&df1, left_key, &df2, right_key, &pool)?;
-    matchdat.sort_by(|a, b| (a.0, a.1).cmp(&(b.0, b.1)));
+        let lsh = match options.list("lsh")? {
+            Some(lsh) => {
+                let lsh = Options::new(lsh);
+                match (lsh.get::<i32>("n_hashes")?, lsh.get::<i32>("n_bands")?) {
+                    (Some(n_hashes), Some(n_bands)) => {
+                        let n_hashes = usize::try_from(n_hashes)
+                            .map_err(|_| anyhow!("`n_hashes` must be a positive integer"))?;
+                        let n_bands = usize::try_from(n_bands)
+                            .map_err(|_| anyhow!("`n_bands` must be a positive integer"))?;
+                        let seed = match lsh.get::<f64>("seed")? {
+                            Some(seed)
+                                if seed >= 0.0 && seed.fract() == 0.0 && seed < 2f64.powi(64) =>
+                            {
+                                seed as u64
+                            }
+                            Some(_) => {
+                                return Err(anyhow!("`seed` must be a non-negative whole number"))
+                            }
+                            None => 0,
+                        };
+                        Some(MinHashLsh::new(n_hashes, n_bands, seed)?)
+                    }
+                    _ => return Err(anyhow!("LSH requires both `n_hashes` and `n_bands`")),
+                }
+            }
+            None => None,
+        };
 
-    let mut idxs1 = Vec::with_capacity(matchdat.len());
-    let mut idxs2 = Vec::with_capacity(matchdat.len());
-    let mut dists = Vec::with_capacity(matchdat.len());
+        // One method per key column. The first one finds candidate pairs and
+        // the others filter them. LSH only ever applies to the first column.
+        let join_methods: Vec<JoinMethod> = match options.list("specs")? {
+            Some(specs) => {
+                if specs.len() != keys.len() {
+                    return Err(anyhow!(
+                        "Expected {} method specs (one per join column), got {}",
+                        keys.len(),
+                        specs.len()
+                    ));
+                }
+                specs
+                    .values()
+                    .enumerate()
+                    .map(|(i, spec)| method_from_spec(&spec, lsh.filter(|_| i == 0)))
+                    .collect::<Result<_>>()?
+            }
+            None => {
+                let method = options
+                    .get::<String>("method")?
+                    .ok_or_else(|| anyhow!("Must provide `method`"))?;
+                let max_distance = options
+                    .get::<f64>("max_distance")?
+                    .ok_or_else(|| anyhow!("Must provide `max_distance`"))?;
+                let q = options.get::<i32>("q")?.map(|x| x as usize);
+                let max_prefix = options.get::<i32>("max_prefix")?.map(|x| x as usize);
+                let prefix_weight = options.get::<f64>("prefix_weight")?;
+                (0..keys.len())
+                    .map(|i| {
+                        get_join_method(
+                            &method,
+                            max_distance,
+                            q,
+                            prefix_weight,
+                            max_prefix,
+                            lsh.filter(|_| i == 0),
+                        )
+                    })
+                    .collect::<Result<_>>()?
+            }
+        };
 
-    for (i, j, d) in matchdat {
-        idxs1.push(i);
-        idxs2.push(j);
-        dists.push(d);
+        let max_distances: Vec<f64> = join_methods.iter().map(JoinMethod::max_distance).collect();
+        let composite = CompositeScore::new(
+            options.get("weights")?,
+            options.get("max_score")?,
+            &max_distances,
+        )?;
+
+        let spec = StringSpec {
+            keys,
+            join_methods,
+            composite,
+            top_k,
+        };
+
+        // Otherwise best matches are picked only after the first column has
+        // compared every pair of rows
+        if spec.top_k.is_some()
+            && !spec.single()
+            && !spec.join_methods[0].max_distance().is_finite()
+        {
+            return Err(anyhow!(
+                "With several `by` columns or `weights`, `k` needs a finite `max_distance` for the first column"
+            ));
+        }
+        Ok(spec)
     }
-    let out: List = if keys.len() == 1 {
-        let dists = DistanceData::Single(&dists);
-        let joined = dispatch_join(
-            how.as_str(),
-            &df1,
-            &df2,
-            idxs1,
-            idxs2,
-            distance_col,
-            dists,
-            by,
-        );
-        joined
-    } else {
+
+    // With several key columns or a composite score, best matches can only
+    // be chosen once every pair is scored, so the first column keeps all its
+    // candidates
+    fn single(&self) -> bool {
+        self.keys.len() == 1 && self.composite.is_none()
+    }
+
+    // Matched pairs of a string join, before they are merged into the
+    // output. In a self-join, `df1` and `df2` are the same table and only
+    // pairs with i < j are found.
+    fn pairs(
+        &self,
+        df1: &List,
+        df2: &List,
+        block_by: Option<&List>,
+        pairing: Pairing,
+        pool: &rayon::ThreadPool,
+    ) -> Result<Matches> {
+        let cols1: Vec<&str> = self.keys.iter().map(|(lk, _)| lk.as_str()).collect();
+        let cols2: Vec<&str> = self.keys.iter().map(|(_, rk)| rk.as_str()).collect();
+        let ndists = self.keys.len() + usize::from(self.composite.is_some());
+        blocked_matches(df1, df2, block_by, &cols1, &cols2, ndists, |df1, df2| {
+            self.matches(df1, df2, pairing, pool)
+        })
+    }
+
+    // Matched pairs with one distance vector per key column, plus the
+    // composite score last when there is one. With blocking this runs once
+    // per block.
+    fn matches(
+        &self,
+        df1: &List,
+        df2: &List,
+        pairing: Pairing,
+        pool: &rayon::ThreadPool,
+    ) -> Result<Matches> {
+        let (keys, join_methods) = (&self.keys, &self.join_methods);
+        let top_k = self.top_k.as_ref();
+        let single = self.single();
+        let select = Selection {
+            top_k: if single { top_k } else { None },
+            pairing,
+        };
+        let (left_key, right_key) = &keys[0];
+
+        let mut matchdat =
+            join_methods[0].fuzzy_indices(df1, left_key, df2, right_key, select, pool)?;
+        matchdat.sort_by_key(|&(i, j, _)| (i, j));
+
+        let mut idxs1 = Vec::with_capacity(matchdat.len());
+        let mut idxs2 = Vec::with_capacity(matchdat.len());
+        let mut dists = Vec::with_capacity(matchdat.len());
+
+        for (i, j, d) in matchdat {
+            idxs1.push(i);
+            idxs2.push(j);
+            dists.push(d);
+        }
         let mut dists = vec![dists];
 
-        for bypair in keys[1..].iter() {
-            (idxs1, idxs2, dists) = difference_pairs(
-                &df1,
-                &idxs1,
-                &df2,
-                &idxs2,
-                &bypair,
-                &dists,
-                max_distance,
-                &method,
-                qz,
-                max_prefix,
-                prefix_weight,
-                &pool,
-            )?
+        if single {
+            return Ok((idxs1, idxs2, dists));
         }
 
-        let dists = DistanceData::Matrix(&dists);
-        let joined = dispatch_join(
-            how.as_str(),
-            &df1,
-            &df2,
-            idxs1,
-            idxs2,
-            distance_col,
-            dists,
-            by,
-        );
-        joined
-    };
-    Ok(out)
+        if let Some(composite) = &self.composite {
+            // Score every candidate from the first column on all columns,
+            // then filter on the weighted score rather than on each column
+            for (bypair, join_method) in keys[1..].iter().zip(&join_methods[1..]) {
+                dists.push(pair_distances(
+                    df1,
+                    &idxs1,
+                    df2,
+                    &idxs2,
+                    bypair,
+                    join_method,
+                    pool,
+                )?);
+            }
+
+            let max_distances: Vec<f64> =
+                join_methods.iter().map(JoinMethod::max_distance).collect();
+            dists.push(composite.scores(&dists, &max_distances));
+            let keep = composite.passing(&dists[keys.len()]);
+            (idxs1, idxs2, dists) = subset_pairs(&idxs1, &idxs2, &dists, &keep);
+
+            // Rank the surviving pairs by their composite score
+            if let Some(top_k) = top_k {
+                let keep = keep_best_rows(&idxs1, &idxs2, &dists[keys.len()], top_k);
+                (idxs1, idxs2, dists) = subset_pairs(&idxs1, &idxs2, &dists, &keep);
+            }
+        } else {
+            for (bypair, join_method) in keys[1..].iter().zip(&join_methods[1..]) {
+                (idxs1, idxs2, dists) =
+                    difference_pairs(df1, &idxs1, df2, &idxs2, bypair, &dists, join_method, pool)?
+            }
+
+            // Rank the surviving pairs by the distance on the first column
+            if let Some(top_k) = top_k {
+                let keep = keep_best_rows(&idxs1, &idxs2, &dists[0], top_k);
+                (idxs1, idxs2, dists) = subset_pairs(&idxs1, &idxs2, &dists, &keep);
+            }
+        }
+
+        Ok((idxs1, idxs2, dists))
+    }
 }
 
 pub fn difference_pairs(
@@ -135,11 +341,7 @@ pub fn difference_pairs(
     idxs2: &Vec<usize>,
     by: &(String, String),
     dists: &Vec<Vec<f64>>,
-    max_distance: f64,
-    method: &str,
-    q: Option<usize>,
-    max_prefix: Option<usize>,
-    prefix_weight: Option<f64>,
+    join_method: &JoinMethod,
     pool: &rayon::ThreadPool,
 ) -> Result<(Vec<usize>, Vec<usize>, Vec<Vec<f64>>)> {
     let lk = by.0.as_str();
@@ -165,7 +367,6 @@ pub fn difference_pairs(
         .as_str_vector()
         .ok_or_else(|| anyhow!("Failed to convert df2 column '{}' to string vector", rk))?;
 
-    let join_method = get_join_method(method, max_distance, q, prefix_weight, max_prefix)?;
     let (idxs0, newdist) = join_method.compare_pairs(&vec1, &vec2, pool)?;
     let (idxs1b, idxs2b) = { idxs0.iter().map(|&i| (idxs1[i], idxs2[i])).unzip() };
 
@@ -177,3 +378,46 @@ pub fn difference_pairs(
     dists_out.push(newdist);
     Ok((idxs1b, idxs2b, dists_out))
 }
+
+// Distance on one more column for every candidate pair, with `NA` where the
+// pair fails that column's own comparison
+pub fn pair_distances(
+    df1: &List,
+    idxs1: &Vec<usize>,
+    df2: &List,
+    idxs2: &Vec<usize>,
+    by: &(String, String),
+    join_method: &JoinMethod,
+    pool: &rayon::ThreadPool,
+) -> Result<Vec<f64>> {
+    let lk = by.0.as_str();
+    let rk = by.1.as_str();
+
+    let vec1_binding = df1
+        .dollar(lk)
+        .map_err(|_| anyhow!("Missing column '{}' in df1", lk))?
+        .slice(idxs1)
+        .map_err(|_| anyhow!("Failed to slice df1 column '{}'", lk))?;
+
+    let vec1: Vec<&str> = vec1_binding
+        .as_str_vector()
+        .ok_or_else(|| anyhow!("Failed to convert df1 column '{}' to string vector", lk))?;
+
+    let vec2_binding = df2
+        .dollar(rk)
+        .map_err(|_| anyhow!("Missing column '{}' in df2", rk))?
+        .slice(idxs2)
+        .map_err(|_| anyhow!("Failed to slice df2 column '{}'", rk))?;
+
+    let vec2: Vec<&str> = vec2_binding
+        .as_str_vector()
+        .ok_or_else(|| anyhow!("Failed to convert df2 column '{}' to string vector", rk))?;
+
+    let (idxs0, newdist) = join_method.compare_pairs(&vec1, &vec2, pool)?;
+
+    let mut out = vec![f64::na(); idxs1.len()];
+    for (i, d) in idxs0.into_iter().zip(newdist) {
+        out[i] = d;
+    }
+    Ok(out)
+}
@@ -2,6 +2,7 @@
 // Source: https://docs.rs/textdistance/latest/textdistance/
 // License: MIT
 
+use crate::string::ngram::prefix::PrefixMeasure;
 use crate::string::ngram::QGramDistance;
 use rustc_hash::FxHashMap;
 
@@ -37,4 +38,10 @@ impl QGramDistance for Cosine {
         let similarity = dot_product as f64 / (norm_s1 as f64).sqrt() / (norm_s2 as f64).sqrt();
         1.0 - similarity // Convert similarity to edit distance
     }
+
+    fn prefix_measure(&self, max_distance: f64) -> Option<PrefixMeasure> {
+        Some(PrefixMeasure::Cosine {
+            threshold: 1.0 - max_distance,
+        })
+    }
 }
@@ -9,12 +9,15 @@ use rayon::ThreadPool;
 use rustc_hash::{FxHashMap, FxHashSet};
 use std::collections::VecDeque;
 
+use crate::string::ngram::prefix::{PrefixIndex, PrefixMeasure};
 use crate::string::ngram::QGramDistance;
+use crate::topk::{keep_best, Selection};
+use crate::utils::{robj_index_map, KeyColumn};
 
 // Jaccard Distance Implementation
 pub struct Jaccard;
 
-fn get_qgram_set(s: &str, q: usize) -> FxHashSet<&str> {
+pub fn get_qgram_set(s: &str, q: usize) -> FxHashSet<&str> {
     let mut grams = FxHashSet::default();
     let mut ring = VecDeque::with_capacity(q + 1);
 
@@ -37,6 +40,18 @@ fn get_qgram_set(s: &str, q: usize) -> FxHashSet<&str> {
     grams
 }
 
+// Jaccard distance between two q-gram sets. Keys too short to have any
+// q-grams share nothing with other keys, so they are at distance 1.
+fn set_distance(x: &FxHashSet<&str>, y: &FxHashSet<&str>) -> f64 {
+    let nmatch = x.intersection(y).count();
+    let union = x.len() + y.len() - nmatch;
+    if union == 0 {
+        1.0
+    } else {
+        1.0 - (nmatch as f64 / union as f64)
+    }
+}
+
 impl QGramDistance for Jaccard {
     fn compute(
         &self,
@@ -81,15 +96,10 @@ impl QGramDistance for Jaccard {
                         return None;
                     }
 
-                    let hs1 = get_qgram_set(l, *q);
-                    let hs2 = get_qgram_set(r, *q);
-
-                    let dist = if hs1.is_empty() && hs2.is_empty() {
+                    let dist = if l == r {
                         0.0
                     } else {
-                        let intersection = hs1.intersection(&hs2).count();
-                        let union = hs1.union(&hs2).count();
-                        1.0 - (intersection as f64 / union as f64)
+                        set_distance(&get_qgram_set(l, *q), &get_qgram_set(r, *q))
                     };
 
                     if dist <= *max_distance {
@@ -104,118 +114,70 @@ impl QGramDistance for Jaccard {
         (keep, dists)
     }
 
+    fn prefix_measure(&self, max_distance: f64) -> Option<PrefixMeasure> {
+        // Best-match joins without a cap pass an infinite distance; a similarity
+        // below 0 would make the size filter NaN for keys without q-grams
+        Some(PrefixMeasure::Jaccard {
+            threshold: (1.0 - max_distance).clamp(0.0, 1.0),
+        })
+    }
+
     fn fuzzy_indices(
         &self,
-        left: &List,
-        left_key: &str,
-        right: &List,
-        right_key: &str,
+        left: KeyColumn,
+        right: KeyColumn,
         max_distance: f64,
         q: usize,
+        select: Selection,
         pool: &ThreadPool,
     ) -> Result<Vec<(usize, usize, f64)>> {
-        let right_iter = right
-            .dollar(right_key)
-            .map_err(|_| anyhow!("Column '{}' not found in right dataframe", right_key))?
-            .as_str_iter()
-            .ok_or_else(|| anyhow!("Column '{}' is not a string vector", right_key))?;
-
-        // Build RHS q-gram reverse index
-        let mut rhs_max_qgrams: usize = 0;
-        let mut rhs_index: FxHashMap<usize, FxHashMap<&str, Vec<usize>>> = FxHashMap::default();
-        right_iter.enumerate().for_each(|(i, x)| {
-            if !x.is_na() {
-                let grams = get_qgram_set(x, q);
-                let grams_len = grams.len();
-                rhs_max_qgrams = rhs_max_qgrams.max(grams_len);
-                let map = rhs_index
-                    .entry(grams_len)
-                    .or_insert_with(FxHashMap::default);
-
-                for &gram in &grams {
-                    map.entry(gram).or_insert_with(Vec::new).push(i);
-                }
-            }
-        });
+        let map1 = robj_index_map(left.df, left.key)?;
+        let map2 = robj_index_map(right.df, right.key)?;
+
+        let rhs_grams: FxHashMap<&str, FxHashSet<&str>> = map2
+            .keys()
+            .filter(|k| !k.is_na())
+            .map(|k| (*k, get_qgram_set(k, q)))
+            .collect();
 
-        let left_vals = left
-            .dollar(left_key)
-            .map_err(|_| anyhow!("Column '{}' not found in right dataframe", right_key))?;
-        let left_vals = left_vals
-            .as_str_vector()
-            .ok_or_else(|| anyhow!("Column '{}' is not a string vector", right_key))?;
+        let measure = self
+            .prefix_measure(max_distance)
+            .ok_or_else(|| anyhow!("Jaccard distance requires a prefix measure"))?;
+        let index = PrefixIndex::new(rhs_grams.keys().copied(), q, measure);
 
         let out_vals: Vec<(usize, usize, f64)> = pool.install(|| {
-            left_vals
-                .par_iter()
-                .enumerate()
-                .filter_map(|(l_idx, val)| {
-                    let left_grams = if val.is_na() {
+            map1.par_iter()
+                .filter_map(|(k1, v1)| {
+                    if k1.is_na() {
                         return None;
-                    } else {
-                        get_qgram_set(val, q)
-                    };
-                    let lhs_len = left_grams.len();
-
-                    if (lhs_len == 0) & (max_distance.lt(&1.0)) {
-                        return None; // Skip empty q-grams
                     }
+                    let left_grams = get_qgram_set(k1, q);
 
-                    let mut max_q = ((lhs_len as f64) / (1.0 - max_distance)).ceil() as usize;
-                    max_q = max_q.min(rhs_max_qgrams);
-
-                    let min_q = std::cmp::max(
-                        1,
-                        lhs_len.saturating_sub((lhs_len as f64 * max_distance).floor() as usize),
-                    );
+                    // An identical key always matches, even when it is too
+                    // short to have any q-grams
+                    let mut candidates = index.candidates(k1);
+                    if let Some((k2, _)) = rhs_grams.get_key_value(k1) {
+                        candidates.insert(k2);
+                    }
 
                     let mut out: Vec<(usize, usize, f64)> = Vec::new();
-
-                    for rhs_len in min_q..=max_q {
-                        let rhs_qgram_idxs = match rhs_index.get(&rhs_len) {
-                            Some(x) => x,
-                            None => continue,
-                        };
-
-                        let mut candidates: FxHashMap<usize, usize> = FxHashMap::default();
-
-                        if max_distance.lt(&1.0) {
-                            // Only include those with 1+ matches: for each left qgram, add matches (no-op if none)
-                            for qgram in &left_grams {
-                                if let Some(matches) = rhs_qgram_idxs.get(qgram) {
-                                    for &matched_idx in matches {
-                                        *candidates.entry(matched_idx).or_insert(0) += 1;
-                                    }
-                                }
-                            }
-                        } else {
-                            // Must return all matches: iterate all right qgrams; for each right qgram,
-                            // if there's no corresponding left qgram, insert zero for each right index.
-                            for (right_qgram, matches) in rhs_qgram_idxs {
-                                if left_grams.contains(right_qgram) {
-                                    for &matched_idx in matches {
-                                        *candidates.entry(matched_idx).or_insert(0) += 1;
-                                    }
-                                } else {
-                                    for &matched_idx in matches {
-                                        // Insert 0 when left qgram not found for this right qgram
-                                        candidates.entry(matched_idx).or_insert(0);
-                                    }
-                                }
-                            }
+                    // A self-join compares each pair of keys once
+                    for k2 in candidates {
+                        if !select.pairing.compares_keys(k1, k2) {
+                            continue;
                         }
-
-                        // Evaluate distance for each candidate
-                        for (r_idx, nmatch) in candidates {
-                            let denom = lhs_len + rhs_len - nmatch;
-                            let dist = 1.0 - (nmatch as f64 / denom as f64);
-                            if dist <= max_distance {
-                                out.push((l_idx + 1, r_idx + 1, dist));
-                            }
+                        let dist = if *k1 == k2 {
+                            0.0
+                        } else {
+                            set_distance(&left_grams, &rhs_grams[k2])
+                        };
+                        if dist <= max_distance {
+                            out.extend(select.pairing.key_rows(v1, &map2[k2], dist));
                         }
                     }
                     Some(out)
                 })
+                .map(|idxs| keep_best(idxs, select.top_k))
                 .flatten()
                 .collect()
         });
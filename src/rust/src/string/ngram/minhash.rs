@@ -0,0 +1,176 @@
+// Approximate Jaccard joins with MinHash locality-sensitive hashing (Broder,
+// "On the resemblance and containment of documents"). Each q-gram set is
+// summarised by `n_hashes` minimum hash values. The signature is cut into
+// `n_bands` bands, and two keys become candidates when any band hashes to the
+// same bucket. Candidates are verified with the exact Jaccard distance, so
+// the result never contains false positives, but pairs whose signatures
+// never collide are missed.
+
+use crate::string::ngram::jaccard::get_qgram_set;
+use crate::topk::{keep_best, Selection};
+use crate::utils::{robj_index_map, KeyColumn};
+use anyhow::{anyhow, Result};
+use extendr_api::prelude::*;
+use rayon::prelude::*;
+use rayon::ThreadPool;
+use rustc_hash::{FxHashMap, FxHashSet, FxHasher};
+use std::hash::{Hash, Hasher};
+
+// Mersenne prime 2^61 - 1 for the universal hash family
+const MERSENNE_61: u64 = (1 << 61) - 1;
+
+#[derive(Debug, Clone, Copy)]
+pub struct MinHashLsh {
+    pub n_hashes: usize,
+    pub n_bands: usize,
+    pub seed: u64,
+}
+
+impl MinHashLsh {
+    pub fn new(n_hashes: usize, n_bands: usize, seed: u64) -> Result<Self> {
+        if n_hashes == 0 || n_bands == 0 {
+            return Err(anyhow!("`n_hashes` and `n_bands` must be positive"));
+        }
+        if n_hashes % n_bands != 0 {
+            return Err(anyhow!(
+                "`n_hashes` ({}) must be a multiple of `n_bands` ({})",
+                n_hashes,
+                n_bands
+            ));
+        }
+        Ok(MinHashLsh {
+            n_hashes,
+            n_bands,
+            seed,
+        })
+    }
+
+    // Coefficients (a, b) of h(x) = (a * x + b) mod p, drawn from a
+    // splitmix64 stream so the same seed always gives the same functions
+    fn hash_params(&self) -> Vec<(u64, u64)> {
+        let mut state = self.seed;
+        let mut next = || {
+            state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+            let mut z = state;
+            z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+            z ^ (z >> 31)
+        };
+
+        (0..self.n_hashes)
+            .map(|_| (next() % (MERSENNE_61 - 1) + 1, next() % MERSENNE_61))
+            .collect()
+    }
+
+    fn signature(&self, grams: &FxHashSet<&str>, params: &[(u64, u64)]) -> Vec<u64> {
+        let base: Vec<u64> = grams
+            .iter()
+            .map(|gram| {
+                let mut hasher = FxHasher::default();
+                gram.hash(&mut hasher);
+                hasher.finish() % MERSENNE_61
+            })
+            .collect();
+
+        params
+            .iter()
+            .map(|&(a, b)| {
+                base.iter()
+                    .map(|&x| ((a as u128 * x as u128 + b as u128) % MERSENNE_61 as u128) as u64)
+                    .min()
+                    .unwrap_or(u64::MAX)
+            })
+            .collect()
+    }
+
+    fn band_keys(&self, signature: &[u64]) -> Vec<(usize, u64)> {
+        let rows = self.n_hashes / self.n_bands;
+        signature
+            .chunks(rows)
+            .enumerate()
+            .map(|(band, values)| {
+                let mut hasher = FxHasher::default();
+                values.hash(&mut hasher);
+                (band, hasher.finish())
+            })
+            .collect()
+    }
+
+    pub fn fuzzy_indices(
+        &self,
+        left: KeyColumn,
+        right: KeyColumn,
+        max_distance: f64,
+        q: usize,
+        select: Selection,
+        pool: &ThreadPool,
+    ) -> Result<Vec<(usize, usize, f64)>> {
+        let map1 = robj_index_map(left.df, left.key)?;
+        let map2 = robj_index_map(right.df, right.key)?;
+        let params = self.hash_params();
+
+        // Keys without any q-grams have no signature, so unlike the exact
+        // join they are left out even when `max_distance` is 1
+        let rhs: Vec<(&str, FxHashSet<&str>)> = map2
+            .keys()
+            .filter(|k| !k.is_na())
+            .map(|k| (*k, get_qgram_set(k, q)))
+            .filter(|(_, grams)| !grams.is_empty())
+            .collect();
+
+        let rhs_bands: Vec<Vec<(usize, u64)>> = pool.install(|| {
+            rhs.par_iter()
+                .map(|(_, grams)| self.band_keys(&self.signature(grams, &params)))
+                .collect()
+        });
+
+        let mut buckets: FxHashMap<(usize, u64), Vec<usize>> = FxHashMap::default();
+        for (idx, bands) in rhs_bands.into_iter().enumerate() {
+            for band in bands {
+                buckets.entry(band).or_default().push(idx);
+            }
+        }
+
+        let out_vals: Vec<(usize, usize, f64)> = pool.install(|| {
+            map1.par_iter()
+                .filter_map(|(k1, v1)| {
+                    if k1.is_na() {
+                        return None;
+                    }
+                    let left_grams = get_qgram_set(k1, q);
+                    if left_grams.is_empty() {
+                        return None;
+                    }
+
+                    let signature = self.signature(&left_grams, &params);
+                    let mut candidates: FxHashSet<usize> = FxHashSet::default();
+                    for band in self.band_keys(&signature) {
+                        if let Some(idxs) = buckets.get(&band) {
+                            candidates.extend(idxs.iter().copied());
+                        }
+                    }
+
+                    let mut out: Vec<(usize, usize, f64)> = Vec::new();
+                    for idx in candidates {
+                        let (k2, right_grams) = &rhs[idx];
+                        // A self-join compares each pair of keys once
+                        if !select.pairing.compares_keys(k1, k2) {
+                            continue;
+                        }
+                        let nmatch = left_grams.intersection(right_grams).count();
+                        let denom = left_grams.len() + right_grams.len() - nmatch;
+                        let dist = 1.0 - (nmatch as f64 / denom as f64);
+                        if dist <= max_distance {
+                            out.extend(select.pairing.key_rows(v1, &map2[k2], dist));
+                        }
+                    }
+                    Some(out)
+                })
+                .map(|idxs| keep_best(idxs, select.top_k))
+                .flatten()
+                .collect()
+        });
+
+        Ok(out_vals)
+    }
+}
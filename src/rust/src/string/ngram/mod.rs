@@ -1,17 +1,28 @@
-use crate::utils::{get_qgrams, robj_index_map, strvec_to_qgram_map};
+use crate::dedupe::Pairing;
+use crate::string::ngram::prefix::{PrefixIndex, PrefixMeasure};
+use crate::topk::{keep_best, Selection};
+use crate::utils::{get_qgrams, robj_index_map, strvec_to_qgram_map, KeyColumn};
 use extendr_api::prelude::*;
-use itertools::iproduct;
 use rayon::prelude::*;
 use rayon::ThreadPool;
 use rustc_hash::FxHashMap;
 pub mod cosine;
 pub mod jaccard;
+pub mod minhash;
+pub mod prefix;
 pub mod qgram;
 
 // Define a trait for string distance calculations
 pub trait QGramDistance: Send + Sync {
     fn compute(&self, s1: &FxHashMap<&str, usize>, s2: &FxHashMap<&str, usize>) -> f64;
 
+    // Filtering bounds for this measure at the given threshold. Returning
+    // `Some` lets `fuzzy_indices` probe a prefix index instead of comparing
+    // every left key against every right key.
+    fn prefix_measure(&self, _max_distance: f64) -> Option<PrefixMeasure> {
+        None
+    }
+
     fn compare_pairs(
         &self,
         left: &Vec<&str>,
@@ -43,25 +54,41 @@ pub trait QGramDistance: Send + Sync {
     }
     fn fuzzy_indices(
         &self,
-        left: &List,
-        left_key: &str,
-        right: &List,
-        right_key: &str,
+        left: KeyColumn,
+        right: KeyColumn,
         max_distance: f64,
         q: usize,
+        select: Selection,
         pool: &ThreadPool,
     ) -> anyhow::Result<Vec<(usize, usize, f64)>> {
-        let map1 = robj_index_map(&left, &left_key)?;
+        let map1 = robj_index_map(left.df, left.key)?;
 
         // This map uses qgrams as keys and keeps track of both frequencies
         // and the number of occurrences of each qgram
-        let map2_qgrams = strvec_to_qgram_map(right, right_key, q)?;
+        let map2_qgrams = strvec_to_qgram_map(right.df, right.key, q)?;
+
+        let index = self
+            .prefix_measure(max_distance)
+            .map(|measure| PrefixIndex::new(map2_qgrams.keys().copied(), q, measure));
 
         let idxs: Vec<(usize, usize, f64)> = pool.install(|| {
             map1.par_iter()
-                .filter_map(|(k1, v1)| {
-                    let out = self.compare_one_to_many(k1, v1, &map2_qgrams, q, max_distance);
-                    out
+                .map(|(k1, v1)| {
+                    let idxs = self
+                        .matching_keys(
+                            k1,
+                            &map2_qgrams,
+                            index.as_ref(),
+                            q,
+                            max_distance,
+                            select.pairing,
+                        )
+                        .into_iter()
+                        .flat_map(|(k2, dist)| {
+                            select.pairing.key_rows(v1, &map2_qgrams[k2].1, dist)
+                        })
+                        .collect();
+                    keep_best(idxs, select.top_k)
                 })
                 .flatten()
                 .collect()
@@ -69,41 +96,52 @@ pub trait QGramDistance: Send + Sync {
         Ok(idxs)
     }
 
-    fn compare_one_to_many(
+    // Right-hand keys within `max_distance` of `k1`, with their distance
+    fn matching_keys<'a>(
         &self,
         k1: &str,
-        v1: &Vec<usize>,
-        map2_qgrams: &FxHashMap<&str, (FxHashMap<&str, usize>, Vec<usize>)>,
+        map2_qgrams: &'a FxHashMap<&'a str, (FxHashMap<&'a str, usize>, Vec<usize>)>,
+        index: Option<&PrefixIndex<'a>>,
         q: usize,
         max_distance: f64,
-    ) -> Option<Vec<(usize, usize, f64)>> {
+        pairing: Pairing,
+    ) -> Vec<(&'a str, f64)> {
+        let mut matched: Vec<(&str, f64)> = Vec::new();
         if k1.is_na() {
-            return None;
+            return matched;
         }
 
-        let mut idxs: Vec<(usize, usize, f64)> = Vec::new();
         let qg1 = get_qgrams(k1, q);
 
-        for (k2, (qg2, v2)) in map2_qgrams.iter() {
-            if &k1 == k2 {
-                iproduct!(v1, v2).for_each(|(v1, v2)| {
-                    idxs.push((*v1, *v2, 0.));
-                });
+        // Only verify keys surviving the prefix filters. An identical key
+        // always matches, even when it is too short to have any q-grams.
+        let candidates: Vec<&str> = match index {
+            Some(index) => {
+                let mut candidates = index.candidates(k1);
+                if let Some((k2, _)) = map2_qgrams.get_key_value(k1) {
+                    candidates.insert(k2);
+                }
+                candidates.into_iter().collect()
+            }
+            None => map2_qgrams.keys().copied().collect(),
+        };
+
+        // A self-join compares each pair of keys once
+        for k2 in candidates {
+            if k2.is_na() || !pairing.compares_keys(k1, k2) {
                 continue;
             }
 
-            let dist = self.compute(&qg1, &qg2) as f64;
-            if dist <= max_distance {
-                iproduct!(v1, v2).for_each(|(a, b)| {
-                    idxs.push((*a, *b, dist));
-                });
+            if k1 == k2 {
+                matched.push((k2, 0.));
+                continue;
             }
-        }
 
-        if idxs.is_empty() {
-            None
-        } else {
-            Some(idxs)
+            let dist = self.compute(&qg1, &map2_qgrams[k2].0) as f64;
+            if dist <= max_distance {
+                matched.push((k2, dist));
+            }
         }
+        matched
     }
 }
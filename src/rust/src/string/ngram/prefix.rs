@@ -0,0 +1,296 @@
+// Prefix and position filtering for q-gram similarity joins, following
+// PPJoin (Xiao et al., "Efficient Similarity Joins for Near Duplicate
+// Detection"). Every q-gram token is ranked by how rarely it occurs among the
+// right-hand keys and each key's tokens are sorted by that global order. Two
+// keys that pass the threshold must then share a token within the first few
+// (rarest) tokens of both keys, so only those prefixes are indexed and probed.
+// The position filter drops a candidate as soon as the tokens left after the
+// current position can no longer make up the required overlap. Survivors are
+// verified with the exact distance by the caller.
+
+use crate::string::ngram::jaccard::get_qgram_set;
+use crate::utils::get_qgrams;
+use extendr_api::prelude::*;
+use rustc_hash::{FxHashMap, FxHashSet};
+
+// Relative slack so that rounding never turns a true match into a miss
+const EPS: f64 = 1e-9;
+
+// A q-gram plus its occurrence number. Multiset measures treat the second
+// "ab" in a string as a different token from the first, which makes the
+// multiset intersection an ordinary set intersection.
+pub type Token<'a> = (&'a str, usize);
+
+#[derive(Debug, Clone, Copy)]
+pub enum PrefixMeasure {
+    // Set similarity |x ∩ y| / |x ∪ y| of at least `threshold`
+    Jaccard { threshold: f64 },
+    // Count-vector cosine similarity of at least `threshold`
+    Cosine { threshold: f64 },
+    // Count-vector L1 distance of at most `max_distance`
+    QGram { max_distance: f64 },
+}
+
+struct Record {
+    // (rank, weight) sorted by rank
+    tokens: Vec<(u32, f64)>,
+    // Set size for Jaccard and q-gram, Euclidean norm for cosine
+    size: f64,
+    // Upper bound on what tokens from each position onwards can contribute
+    rest: Vec<f64>,
+    // Number of leading tokens that must be indexed, or `None` when the
+    // record can satisfy the threshold without sharing any token
+    prefix_len: Option<usize>,
+}
+
+impl PrefixMeasure {
+    pub fn tokens<'a>(&self, s: &'a str, q: usize) -> Vec<(Token<'a>, f64)> {
+        match self {
+            PrefixMeasure::Jaccard { .. } => get_qgram_set(s, q)
+                .into_iter()
+                .map(|gram| ((gram, 0), 1.0))
+                .collect(),
+            PrefixMeasure::Cosine { .. } => get_qgrams(s, q)
+                .into_iter()
+                .map(|(gram, count)| ((gram, 0), count as f64))
+                .collect(),
+            PrefixMeasure::QGram { .. } => get_qgrams(s, q)
+                .into_iter()
+                .flat_map(|(gram, count)| (0..count).map(move |i| ((gram, i), 1.0)))
+                .collect(),
+        }
+    }
+
+    fn is_weighted(&self) -> bool {
+        matches!(self, PrefixMeasure::Cosine { .. })
+    }
+
+    // Minimum overlap (or dot product) two records of these sizes need
+    fn min_overlap(&self, x: f64, y: f64) -> f64 {
+        match self {
+            PrefixMeasure::Jaccard { threshold } => threshold / (1.0 + threshold) * (x + y),
+            PrefixMeasure::Cosine { threshold } => threshold * x * y,
+            PrefixMeasure::QGram { max_distance } => (x + y - max_distance) / 2.0,
+        }
+    }
+
+    // Whether two records of these sizes can pass the threshold at all
+    fn size_compatible(&self, x: f64, y: f64) -> bool {
+        match self {
+            PrefixMeasure::Jaccard { threshold } => {
+                y >= threshold * x * (1.0 - EPS) && y * threshold <= x * (1.0 + EPS)
+            }
+            PrefixMeasure::Cosine { .. } => true,
+            PrefixMeasure::QGram { max_distance } => (x - y).abs() <= max_distance + EPS,
+        }
+    }
+
+    // Best case contribution of everything from the current positions onwards
+    fn remaining(&self, rest_x: f64, rest_y: f64) -> f64 {
+        if self.is_weighted() {
+            rest_x * rest_y
+        } else {
+            rest_x.min(rest_y)
+        }
+    }
+
+    fn record(&self, mut tokens: Vec<(u32, f64)>) -> Record {
+        tokens.sort_by_key(|token| token.0);
+        let n = tokens.len();
+
+        let (size, rest) = if self.is_weighted() {
+            let mut suffix = vec![0.0; n + 1];
+            for i in (0..n).rev() {
+                suffix[i] = suffix[i + 1] + tokens[i].1 * tokens[i].1;
+            }
+            let size = suffix[0].sqrt();
+            (size, suffix.into_iter().map(f64::sqrt).collect::<Vec<_>>())
+        } else {
+            let rest = (0..=n).map(|i| (n - i) as f64).collect::<Vec<_>>();
+            (n as f64, rest)
+        };
+
+        let prefix_len = match self {
+            PrefixMeasure::Jaccard { threshold } => {
+                if *threshold <= 0.0 {
+                    None
+                } else {
+                    let overlap = (threshold * size * (1.0 - EPS)).ceil() as usize;
+                    Some((n + 1).saturating_sub(overlap).min(n))
+                }
+            }
+            PrefixMeasure::QGram { max_distance } => {
+                // The smallest overlap any partner needs is |x| - D
+                let overlap = (size - max_distance) * (1.0 - EPS);
+                if overlap <= 0.0 {
+                    None
+                } else {
+                    Some((n + 1).saturating_sub(overlap.ceil() as usize).min(n))
+                }
+            }
+            PrefixMeasure::Cosine { threshold } => {
+                if *threshold <= 0.0 {
+                    None
+                } else {
+                    // Stop once the norm of the remaining tokens is too small
+                    // to reach the threshold on its own (Cauchy-Schwarz)
+                    let needed = threshold * size * (1.0 - EPS);
+                    Some((0..=n).find(|&i| rest[i] < needed).unwrap_or(n))
+                }
+            }
+        };
+
+        Record {
+            tokens,
+            size,
+            rest,
+            prefix_len,
+        }
+    }
+}
+
+pub struct PrefixIndex<'a> {
+    measure: PrefixMeasure,
+    q: usize,
+    ranks: FxHashMap<Token<'a>, u32>,
+    keys: Vec<&'a str>,
+    records: Vec<Record>,
+    // rank -> (record, position) for prefix tokens only
+    postings: FxHashMap<u32, Vec<(usize, usize)>>,
+    // Records that may match without sharing a token
+    unfiltered: Vec<usize>,
+}
+
+impl<'a> PrefixIndex<'a> {
+    pub fn new<I>(keys: I, q: usize, measure: PrefixMeasure) -> Self
+    where
+        I: IntoIterator<Item = &'a str>,
+    {
+        let tokenized: Vec<(&'a str, Vec<(Token<'a>, f64)>)> = keys
+            .into_iter()
+            .filter(|key| !key.is_na())
+            .map(|key| (key, measure.tokens(key, q)))
+            .collect();
+
+        // Global order: rarest tokens first. Rank 0 is reserved for tokens
+        // that never occur on the right, which therefore sort first.
+        let mut freq: FxHashMap<Token<'a>, usize> = FxHashMap::default();
+        for (_, tokens) in &tokenized {
+            for (token, _) in tokens {
+                *freq.entry(*token).or_insert(0) += 1;
+            }
+        }
+        let mut ordered: Vec<(Token<'a>, usize)> = freq.into_iter().collect();
+        ordered.sort_by(|a, b| a.1.cmp(&b.1).then(a.0.cmp(&b.0)));
+        let ranks: FxHashMap<Token<'a>, u32> = ordered
+            .into_iter()
+            .enumerate()
+            .map(|(i, (token, _))| (token, i as u32 + 1))
+            .collect();
+
+        let mut keys = Vec::with_capacity(tokenized.len());
+        let mut records = Vec::with_capacity(tokenized.len());
+        let mut postings: FxHashMap<u32, Vec<(usize, usize)>> = FxHashMap::default();
+        let mut unfiltered = Vec::new();
+
+        for (key, tokens) in tokenized {
+            let ranked = tokens.into_iter().map(|(t, w)| (ranks[&t], w)).collect();
+            let record = measure.record(ranked);
+            let idx = records.len();
+
+            match record.prefix_len {
+                Some(p) => {
+                    for (pos, (rank, _)) in record.tokens[..p].iter().enumerate() {
+                        postings.entry(*rank).or_default().push((idx, pos));
+                    }
+                }
+                None => unfiltered.push(idx),
+            }
+
+            keys.push(key);
+            records.push(record);
+        }
+
+        PrefixIndex {
+            measure,
+            q,
+            ranks,
+            keys,
+            records,
+            postings,
+            unfiltered,
+        }
+    }
+
+    // Distinct right-hand keys that survive the size, prefix, and position
+    // filters for `key`. Callers still need to verify the exact distance.
+    pub fn candidates(&self, key: &str) -> FxHashSet<&'a str> {
+        let ranked = self
+            .measure
+            .tokens(key, self.q)
+            .into_iter()
+            .map(|(t, w)| (self.ranks.get(&t).copied().unwrap_or(0), w))
+            .collect();
+        let x = self.measure.record(ranked);
+
+        let mut out: FxHashSet<&'a str> = FxHashSet::default();
+
+        // Without a usable prefix every size-compatible record is a candidate
+        let Some(px) = x.prefix_len else {
+            for (idx, y) in self.records.iter().enumerate() {
+                if self.measure.size_compatible(x.size, y.size) {
+                    out.insert(self.keys[idx]);
+                }
+            }
+            return out;
+        };
+
+        for &idx in &self.unfiltered {
+            if self.measure.size_compatible(x.size, self.records[idx].size) {
+                out.insert(self.keys[idx]);
+            }
+        }
+
+        let mut overlap: FxHashMap<usize, f64> = FxHashMap::default();
+        for (i, &(rank, wx)) in x.tokens[..px].iter().enumerate() {
+            // Tokens unseen on the right cannot be shared
+            if rank == 0 {
+                continue;
+            }
+            let Some(postings) = self.postings.get(&rank) else {
+                continue;
+            };
+
+            for &(idx, j) in postings {
+                let y = &self.records[idx];
+                if !self.measure.size_compatible(x.size, y.size) {
+                    continue;
+                }
+
+                let acc = overlap.entry(idx).or_insert(0.0);
+                if *acc == f64::NEG_INFINITY {
+                    continue;
+                }
+
+                let needed = self.measure.min_overlap(x.size, y.size) * (1.0 - EPS);
+                if *acc + self.measure.remaining(x.rest[i], y.rest[j]) >= needed {
+                    *acc += if self.measure.is_weighted() {
+                        wx * y.tokens[j].1
+                    } else {
+                        1.0
+                    };
+                } else {
+                    *acc = f64::NEG_INFINITY;
+                }
+            }
+        }
+
+        for (idx, acc) in overlap {
+            if acc != f64::NEG_INFINITY {
+                out.insert(self.keys[idx]);
+            }
+        }
+
+        out
+    }
+}
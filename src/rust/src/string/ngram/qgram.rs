@@ -2,6 +2,7 @@
 // Source: https://docs.rs/textdistance/latest/textdistance/
 // License: MIT
 
+use crate::string::ngram::prefix::PrefixMeasure;
 use crate::string::ngram::QGramDistance;
 use rustc_hash::FxHashMap;
 
@@ -29,4 +30,8 @@ impl QGramDistance for QGram {
 
         mismatch_count as f64
     }
+
+    fn prefix_measure(&self, max_distance: f64) -> Option<PrefixMeasure> {
+        Some(PrefixMeasure::QGram { max_distance })
+    }
 }
@@ -0,0 +1,101 @@
+use crate::string::phonetic::{Codes, PhoneticEncoder};
+use regex::Regex;
+use std::sync::OnceLock;
+
+// Codes are padded with 1s or cut to ten characters
+const CODE_LEN: usize = 10;
+
+// Caverphone 2.0 (David Hood, 2004), designed for matching names in New
+// Zealand electoral and health records. Upper-case letters in the rules are
+// final, and 2 and 3 mark removed consonants and vowels until the end.
+const RULES: [(&str, &str); 56] = [
+    ("e$", ""),
+    ("^cough", "cou2f"),
+    ("^rough", "rou2f"),
+    ("^tough", "tou2f"),
+    ("^enough", "enou2f"),
+    ("^trough", "trou2f"),
+    ("^gn", "2n"),
+    ("mb$", "m2"),
+    ("cq", "2q"),
+    ("ci", "si"),
+    ("ce", "se"),
+    ("cy", "sy"),
+    ("tch", "2ch"),
+    ("c", "k"),
+    ("q", "k"),
+    ("x", "k"),
+    ("v", "f"),
+    ("dg", "2g"),
+    ("tio", "sio"),
+    ("tia", "sia"),
+    ("d", "t"),
+    ("ph", "fh"),
+    ("b", "p"),
+    ("sh", "s2"),
+    ("z", "s"),
+    ("^[aeiou]", "A"),
+    ("[aeiou]", "3"),
+    ("j", "y"),
+    ("^y3", "Y3"),
+    ("^y", "A"),
+    ("y", "3"),
+    ("3gh3", "3kh3"),
+    ("gh", "22"),
+    ("g", "k"),
+    ("s+", "S"),
+    ("t+", "T"),
+    ("p+", "P"),
+    ("k+", "K"),
+    ("f+", "F"),
+    ("m+", "M"),
+    ("n+", "N"),
+    ("w3", "W3"),
+    ("wh3", "Wh3"),
+    ("w$", "3"),
+    ("w", "2"),
+    ("^h", "A"),
+    ("h", "2"),
+    ("r3", "R3"),
+    ("r$", "3"),
+    ("r", "2"),
+    ("l3", "L3"),
+    ("l$", "3"),
+    ("l", "2"),
+    ("2", ""),
+    ("3$", "A"),
+    ("3", ""),
+];
+
+fn rules() -> &'static Vec<(Regex, &'static str)> {
+    static COMPILED: OnceLock<Vec<(Regex, &'static str)>> = OnceLock::new();
+    COMPILED.get_or_init(|| {
+        RULES
+            .iter()
+            .map(|(pattern, replacement)| (Regex::new(pattern).unwrap(), *replacement))
+            .collect()
+    })
+}
+
+pub struct Caverphone;
+impl PhoneticEncoder for Caverphone {
+    fn encode(&self, s: &str) -> Codes {
+        vec![caverphone(s)]
+    }
+}
+
+pub fn caverphone(s: &str) -> String {
+    let mut word: String = s
+        .chars()
+        .filter(|c| c.is_ascii_alphabetic())
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+
+    for (pattern, replacement) in rules() {
+        word = pattern.replace_all(&word, *replacement).into_owned();
+    }
+
+    word.push_str(&"1".repeat(CODE_LEN));
+    word.truncate(CODE_LEN);
+    word
+}
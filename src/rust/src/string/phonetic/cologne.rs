@@ -0,0 +1,85 @@
+use crate::string::phonetic::{fold_letters, Codes, PhoneticEncoder};
+
+// Kölner Phonetik (Hans Joachim Postel, 1969), a Soundex-like code for
+// German names. Codes have no fixed length.
+pub struct Cologne;
+impl PhoneticEncoder for Cologne {
+    fn encode(&self, s: &str) -> Codes {
+        vec![cologne(s)]
+    }
+}
+
+// Digits for one letter, given its neighbours. H has no digit.
+fn letter_code(prev: Option<char>, c: char, next: Option<char>, first: bool) -> &'static str {
+    let next_in = |set: &str| next.is_some_and(|n| set.contains(n));
+    let prev_in = |set: &str| prev.is_some_and(|p| set.contains(p));
+    match c {
+        'A' | 'E' | 'I' | 'J' | 'O' | 'U' | 'Y' => "0",
+        'B' => "1",
+        'P' => {
+            if next == Some('H') {
+                "3"
+            } else {
+                "1"
+            }
+        }
+        'D' | 'T' => {
+            if next_in("CSZ") {
+                "8"
+            } else {
+                "2"
+            }
+        }
+        'F' | 'V' | 'W' => "3",
+        'G' | 'K' | 'Q' => "4",
+        'C' => {
+            if first {
+                if next_in("AHKLOQRUX") {
+                    "4"
+                } else {
+                    "8"
+                }
+            } else if prev_in("SZ") || !next_in("AHKOQUX") {
+                "8"
+            } else {
+                "4"
+            }
+        }
+        'X' => {
+            if prev_in("CKQ") {
+                "8"
+            } else {
+                "48"
+            }
+        }
+        'L' => "5",
+        'M' | 'N' => "6",
+        'R' => "7",
+        'S' | 'Z' => "8",
+        _ => "",
+    }
+}
+
+pub fn cologne(s: &str) -> String {
+    let letters = fold_letters(s);
+
+    let mut digits: Vec<char> = Vec::new();
+    for (i, &c) in letters.iter().enumerate() {
+        let prev = i.checked_sub(1).map(|j| letters[j]);
+        let next = letters.get(i + 1).copied();
+        for digit in letter_code(prev, c, next, i == 0).chars() {
+            // Repeated digits are coded once
+            if digits.last() != Some(&digit) {
+                digits.push(digit);
+            }
+        }
+    }
+
+    // Zeros only stay at the start
+    digits
+        .iter()
+        .enumerate()
+        .filter(|&(i, &d)| i == 0 || d != '0')
+        .map(|(_, &d)| d)
+        .collect()
+}
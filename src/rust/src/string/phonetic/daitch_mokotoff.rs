@@ -0,0 +1,174 @@
+use crate::string::phonetic::{fold_letters, Codes, PhoneticEncoder};
+
+// Codes are padded with 0s or cut to six digits
+const CODE_LEN: usize = 6;
+
+// Daitch–Mokotoff Soundex (Gary Mokotoff and Randy Daitch, 1985), designed
+// for Slavic, Germanic and Yiddish names. Some letters can be read more than
+// one way, so a name can have several codes.
+pub struct DaitchMokotoff;
+impl PhoneticEncoder for DaitchMokotoff {
+    fn encode(&self, s: &str) -> Codes {
+        daitch_mokotoff(s)
+    }
+}
+
+type Rule = (&'static str, &'static str, &'static str, &'static str);
+
+// Letter groups and their digits at the start of a name, before a vowel and
+// anywhere else. Alternatives are separated by "|", and an empty string means
+// the group is not coded.
+#[rustfmt::skip]
+const RULES: &[Rule] = &[
+    ("AI", "0", "1", ""), ("AJ", "0", "1", ""), ("AY", "0", "1", ""),
+    ("AU", "0", "7", ""),
+    ("A", "0", "", ""),
+    ("B", "7", "7", "7"),
+    ("CHS", "5", "54", "54"),
+    ("CH", "5|4", "5|4", "5|4"),
+    ("CK", "5|45", "5|45", "5|45"),
+    ("CZ", "4", "4", "4"), ("CS", "4", "4", "4"),
+    ("CSZ", "4", "4", "4"), ("CZS", "4", "4", "4"),
+    ("C", "5|4", "5|4", "5|4"),
+    ("DRZ", "4", "4", "4"), ("DRS", "4", "4", "4"),
+    ("DS", "4", "4", "4"), ("DSH", "4", "4", "4"), ("DSZ", "4", "4", "4"),
+    ("DZ", "4", "4", "4"), ("DZH", "4", "4", "4"), ("DZS", "4", "4", "4"),
+    ("D", "3", "3", "3"), ("DT", "3", "3", "3"),
+    ("EI", "0", "1", ""), ("EJ", "0", "1", ""), ("EY", "0", "1", ""),
+    ("EU", "1", "1", ""),
+    ("E", "0", "", ""),
+    ("FB", "7", "7", "7"),
+    ("F", "7", "7", "7"),
+    ("G", "5", "5", "5"),
+    ("H", "5", "5", ""),
+    ("IA", "1", "", ""), ("IE", "1", "", ""), ("IO", "1", "", ""), ("IU", "1", "", ""),
+    ("I", "0", "", ""),
+    ("J", "1|4", "1|4", "1|4"),
+    ("KS", "5", "54", "54"),
+    ("KH", "5", "5", "5"),
+    ("K", "5", "5", "5"),
+    ("L", "8", "8", "8"),
+    ("MN", "66", "66", "66"),
+    ("M", "6", "6", "6"),
+    ("NM", "66", "66", "66"),
+    ("N", "6", "6", "6"),
+    ("OI", "0", "1", ""), ("OJ", "0", "1", ""), ("OY", "0", "1", ""),
+    ("O", "0", "", ""),
+    ("P", "7", "7", "7"), ("PF", "7", "7", "7"), ("PH", "7", "7", "7"),
+    ("Q", "5", "5", "5"),
+    ("R", "9", "9", "9"),
+    ("RZ", "94|4", "94|4", "94|4"), ("RS", "94|4", "94|4", "94|4"),
+    ("SCHTSCH", "2", "4", "4"), ("SCHTSH", "2", "4", "4"), ("SCHTCH", "2", "4", "4"),
+    ("SCH", "4", "4", "4"),
+    ("SHTCH", "2", "4", "4"), ("SHCH", "2", "4", "4"), ("SHTSH", "2", "4", "4"),
+    ("SHT", "2", "43", "43"), ("SCHT", "2", "43", "43"), ("SCHD", "2", "43", "43"),
+    ("SH", "4", "4", "4"),
+    ("STCH", "2", "4", "4"), ("STSCH", "2", "4", "4"), ("SC", "2", "4", "4"),
+    ("STRZ", "2", "4", "4"), ("STRS", "2", "4", "4"), ("STSH", "2", "4", "4"),
+    ("ST", "2", "43", "43"),
+    ("SZCZ", "2", "4", "4"), ("SZCS", "2", "4", "4"),
+    ("SZT", "2", "43", "43"), ("SHD", "2", "43", "43"),
+    ("SZD", "2", "43", "43"), ("SD", "2", "43", "43"),
+    ("SZ", "4", "4", "4"),
+    ("S", "4", "4", "4"),
+    ("TCH", "4", "4", "4"), ("TTCH", "4", "4", "4"), ("TTSCH", "4", "4", "4"),
+    ("TH", "3", "3", "3"),
+    ("TRZ", "4", "4", "4"), ("TRS", "4", "4", "4"),
+    ("TSCH", "4", "4", "4"), ("TSH", "4", "4", "4"),
+    ("TS", "4", "4", "4"), ("TTS", "4", "4", "4"), ("TTSZ", "4", "4", "4"), ("TC", "4", "4", "4"),
+    ("TZ", "4", "4", "4"), ("TTZ", "4", "4", "4"), ("TZS", "4", "4", "4"), ("TSZ", "4", "4", "4"),
+    ("T", "3", "3", "3"),
+    ("UI", "0", "1", ""), ("UJ", "0", "1", ""), ("UY", "0", "1", ""),
+    ("U", "0", "", ""), ("UE", "0", "", ""),
+    ("V", "7", "7", "7"),
+    ("W", "7", "7", "7"),
+    ("X", "5", "54", "54"),
+    ("Y", "1", "", ""),
+    ("ZDZ", "2", "4", "4"), ("ZDZH", "2", "4", "4"), ("ZHDZH", "2", "4", "4"),
+    ("ZD", "2", "43", "43"), ("ZHD", "2", "43", "43"),
+    ("ZH", "4", "4", "4"), ("ZS", "4", "4", "4"), ("ZSCH", "4", "4", "4"), ("ZSH", "4", "4", "4"),
+    ("Z", "4", "4", "4"),
+];
+
+fn is_vowel(c: Option<&char>) -> bool {
+    matches!(c, Some('A' | 'E' | 'I' | 'O' | 'U'))
+}
+
+// The longest letter group starting at `i`
+fn longest_rule(letters: &[char], i: usize) -> Option<&'static Rule> {
+    RULES
+        .iter()
+        .filter(|(pattern, ..)| {
+            pattern.len() <= letters.len() - i
+                && pattern.chars().zip(&letters[i..]).all(|(p, c)| p == *c)
+        })
+        .max_by_key(|(pattern, ..)| pattern.len())
+}
+
+// One reading of the name so far
+#[derive(Clone)]
+struct Branch {
+    code: String,
+    // The digits of the previous letter group. A group is not coded again
+    // straight after the same digits.
+    last: Option<&'static str>,
+}
+
+impl Branch {
+    fn push(&mut self, digits: &'static str, force: bool) {
+        let repeated = self.last.is_some_and(|last| last.ends_with(digits));
+        if (!repeated || force) && self.code.len() < CODE_LEN {
+            self.code.push_str(digits);
+            self.code.truncate(CODE_LEN);
+        }
+        self.last = Some(digits);
+    }
+}
+
+pub fn daitch_mokotoff(s: &str) -> Codes {
+    let letters = fold_letters(s);
+
+    let mut branches = vec![Branch {
+        code: String::new(),
+        last: None,
+    }];
+    let mut i = 0;
+    while i < letters.len() {
+        let Some(&(pattern, at_start, before_vowel, other)) = longest_rule(&letters, i) else {
+            i += 1;
+            continue;
+        };
+        let digits = if i == 0 {
+            at_start
+        } else if is_vowel(letters.get(i + pattern.len())) {
+            before_vowel
+        } else {
+            other
+        };
+
+        // Adjacent M and N are both coded
+        let force = i > 0 && matches!((letters[i - 1], letters[i]), ('M', 'N') | ('N', 'M'));
+
+        let mut next: Vec<Branch> = Vec::new();
+        for branch in &branches {
+            for alternative in digits.split('|') {
+                let mut branch = branch.clone();
+                branch.push(alternative, force);
+                if !next.iter().any(|b| b.code == branch.code) {
+                    next.push(branch);
+                }
+            }
+        }
+        branches = next;
+        i += pattern.len();
+    }
+
+    let mut codes: Codes = Vec::with_capacity(branches.len());
+    for branch in branches {
+        let code = format!("{:0<width$}", branch.code, width = CODE_LEN);
+        if !codes.contains(&code) {
+            codes.push(code);
+        }
+    }
+    codes
+}
@@ -0,0 +1,680 @@
+use crate::string::phonetic::{Codes, PhoneticEncoder};
+
+// Both codes are cut to four characters
+const MAX_CODE_LEN: usize = 4;
+
+// Lawrence Philips' Double Metaphone. Names that are read differently in
+// their language of origin get an alternate code, which is only kept when
+// it differs from the primary one.
+pub struct DoubleMetaphone;
+impl PhoneticEncoder for DoubleMetaphone {
+    fn encode(&self, s: &str) -> Codes {
+        let (primary, alternate) = double_metaphone(s);
+        if alternate == primary {
+            vec![primary]
+        } else {
+            vec![primary, alternate]
+        }
+    }
+}
+
+struct Code {
+    primary: String,
+    alternate: String,
+}
+
+impl Code {
+    fn push(&mut self, primary: &str, alternate: &str) {
+        self.push_primary(primary);
+        self.push_alternate(alternate);
+    }
+
+    fn push_both(&mut self, both: &str) {
+        self.push(both, both);
+    }
+
+    fn push_primary(&mut self, s: &str) {
+        for c in s.chars() {
+            if self.primary.len() < MAX_CODE_LEN {
+                self.primary.push(c);
+            }
+        }
+    }
+
+    fn push_alternate(&mut self, s: &str) {
+        for c in s.chars() {
+            if self.alternate.len() < MAX_CODE_LEN {
+                self.alternate.push(c);
+            }
+        }
+    }
+
+    fn is_complete(&self) -> bool {
+        self.primary.len() >= MAX_CODE_LEN && self.alternate.len() >= MAX_CODE_LEN
+    }
+}
+
+struct Word {
+    chars: Vec<char>,
+    slavo_germanic: bool,
+}
+
+impl Word {
+    fn len(&self) -> usize {
+        self.chars.len()
+    }
+
+    fn last(&self) -> usize {
+        self.chars.len() - 1
+    }
+
+    // Character at a position that may fall outside the word
+    fn at(&self, i: isize) -> Option<char> {
+        usize::try_from(i)
+            .ok()
+            .and_then(|i| self.chars.get(i).copied())
+    }
+
+    fn is_vowel(&self, i: isize) -> bool {
+        matches!(self.at(i), Some('A' | 'E' | 'I' | 'O' | 'U' | 'Y'))
+    }
+
+    // Whether the `len` characters from `start` spell one of `patterns`
+    fn contains(&self, start: isize, len: usize, patterns: &[&str]) -> bool {
+        let Ok(start) = usize::try_from(start) else {
+            return false;
+        };
+        let Some(slice) = self.chars.get(start..start + len) else {
+            return false;
+        };
+        patterns
+            .iter()
+            .any(|p| p.chars().count() == len && p.chars().zip(slice).all(|(a, &b)| a == b))
+    }
+
+    fn germanic_start(&self) -> bool {
+        self.contains(0, 4, &["VAN ", "VON "]) || self.contains(0, 3, &["SCH"])
+    }
+}
+
+pub fn double_metaphone(s: &str) -> (String, String) {
+    let chars: Vec<char> = s.trim().chars().flat_map(char::to_uppercase).collect();
+    let mut code = Code {
+        primary: String::new(),
+        alternate: String::new(),
+    };
+    if chars.is_empty() {
+        return (code.primary, code.alternate);
+    }
+
+    let text: String = chars.iter().collect();
+    let word = Word {
+        slavo_germanic: ["W", "K", "CZ", "WITZ"].iter().any(|p| text.contains(p)),
+        chars,
+    };
+
+    // Silent first letter
+    let mut i: isize = if word.contains(0, 2, &["GN", "KN", "PN", "WR", "PS"]) {
+        1
+    } else {
+        0
+    };
+
+    while !code.is_complete() && (i as usize) < word.len() {
+        let next = word.at(i + 1);
+        i = match word.at(i) {
+            Some('A' | 'E' | 'I' | 'O' | 'U' | 'Y') => {
+                // Only initial vowels are coded, all as "A"
+                if i == 0 {
+                    code.push_both("A");
+                }
+                i + 1
+            }
+            Some('B') => {
+                code.push_both("P");
+                if next == Some('B') {
+                    i + 2
+                } else {
+                    i + 1
+                }
+            }
+            Some('Ç') => {
+                code.push_both("S");
+                i + 1
+            }
+            Some('C') => handle_c(&word, &mut code, i),
+            Some('D') => handle_d(&word, &mut code, i),
+            Some('F') => {
+                code.push_both("F");
+                if next == Some('F') {
+                    i + 2
+                } else {
+                    i + 1
+                }
+            }
+            Some('G') => handle_g(&word, &mut code, i),
+            Some('H') => {
+                // Only kept between vowels or at the start before a vowel
+                if (i == 0 || word.is_vowel(i - 1)) && word.is_vowel(i + 1) {
+                    code.push_both("H");
+                    i + 2
+                } else {
+                    i + 1
+                }
+            }
+            Some('J') => handle_j(&word, &mut code, i),
+            Some('K') => {
+                code.push_both("K");
+                if next == Some('K') {
+                    i + 2
+                } else {
+                    i + 1
+                }
+            }
+            Some('L') => handle_l(&word, &mut code, i),
+            Some('M') => {
+                code.push_both("M");
+                // "MM", and a silent B in "UMB" at the end or before "ER"
+                let umb = word.contains(i - 1, 3, &["UMB"])
+                    && (i + 1 == word.last() as isize || word.contains(i + 2, 2, &["ER"]));
+                if next == Some('M') || umb {
+                    i + 2
+                } else {
+                    i + 1
+                }
+            }
+            Some('N') => {
+                code.push_both("N");
+                if next == Some('N') {
+                    i + 2
+                } else {
+                    i + 1
+                }
+            }
+            Some('Ñ') => {
+                code.push_both("N");
+                i + 1
+            }
+            Some('P') => {
+                if next == Some('H') {
+                    code.push_both("F");
+                    i + 2
+                } else {
+                    code.push_both("P");
+                    if word.contains(i + 1, 1, &["P", "B"]) {
+                        i + 2
+                    } else {
+                        i + 1
+                    }
+                }
+            }
+            Some('Q') => {
+                code.push_both("K");
+                if next == Some('Q') {
+                    i + 2
+                } else {
+                    i + 1
+                }
+            }
+            Some('R') => {
+                // French final "R" after "IE", as in "Rogier"
+                if i == word.last() as isize
+                    && !word.slavo_germanic
+                    && word.contains(i - 2, 2, &["IE"])
+                    && !word.contains(i - 4, 2, &["ME", "MA"])
+                {
+                    code.push_alternate("R");
+                } else {
+                    code.push_both("R");
+                }
+                if next == Some('R') {
+                    i + 2
+                } else {
+                    i + 1
+                }
+            }
+            Some('S') => handle_s(&word, &mut code, i),
+            Some('T') => handle_t(&word, &mut code, i),
+            Some('V') => {
+                code.push_both("F");
+                if next == Some('V') {
+                    i + 2
+                } else {
+                    i + 1
+                }
+            }
+            Some('W') => handle_w(&word, &mut code, i),
+            Some('X') => {
+                if i == 0 {
+                    code.push_both("S");
+                    i + 1
+                } else {
+                    // Silent in a French final "AUX" or "OUX"
+                    let french = i == word.last() as isize
+                        && (word.contains(i - 3, 3, &["IAU", "EAU"])
+                            || word.contains(i - 2, 2, &["AU", "OU"]));
+                    if !french {
+                        code.push_both("KS");
+                    }
+                    if word.contains(i + 1, 1, &["C", "X"]) {
+                        i + 2
+                    } else {
+                        i + 1
+                    }
+                }
+            }
+            Some('Z') => handle_z(&word, &mut code, i),
+            _ => i + 1,
+        };
+    }
+
+    (code.primary, code.alternate)
+}
+
+fn handle_c(word: &Word, code: &mut Code, i: isize) -> isize {
+    if condition_c0(word, i) {
+        // Germanic "ACH", as in "Bacher"
+        code.push_both("K");
+        i + 2
+    } else if i == 0 && word.contains(i, 6, &["CAESAR"]) {
+        code.push_both("S");
+        i + 2
+    } else if word.contains(i, 2, &["CH"]) {
+        handle_ch(word, code, i)
+    } else if word.contains(i, 2, &["CZ"]) && !word.contains(i - 2, 4, &["WICZ"]) {
+        code.push("S", "X");
+        i + 2
+    } else if word.contains(i + 1, 3, &["CIA"]) {
+        code.push_both("X");
+        i + 3
+    } else if word.contains(i, 2, &["CC"]) && !(i == 1 && word.at(0) == Some('M')) {
+        handle_cc(word, code, i)
+    } else if word.contains(i, 2, &["CK", "CG", "CQ"]) {
+        code.push_both("K");
+        i + 2
+    } else if word.contains(i, 2, &["CI", "CE", "CY"]) {
+        if word.contains(i, 3, &["CIO", "CIE", "CIA"]) {
+            code.push("S", "X");
+        } else {
+            code.push_both("S");
+        }
+        i + 2
+    } else {
+        code.push_both("K");
+        if word.contains(i + 1, 2, &[" C", " Q", " G"]) {
+            i + 3
+        } else if word.contains(i + 1, 1, &["C", "K", "Q"])
+            && !word.contains(i + 1, 2, &["CE", "CI"])
+        {
+            i + 2
+        } else {
+            i + 1
+        }
+    }
+}
+
+fn condition_c0(word: &Word, i: isize) -> bool {
+    if word.contains(i, 4, &["CHIA"]) {
+        true
+    } else if i <= 1 || word.is_vowel(i - 2) || !word.contains(i - 1, 3, &["ACH"]) {
+        false
+    } else {
+        let c = word.at(i + 2);
+        (c != Some('I') && c != Some('E')) || word.contains(i - 2, 6, &["BACHER", "MACHER"])
+    }
+}
+
+fn handle_cc(word: &Word, code: &mut Code, i: isize) -> isize {
+    if word.contains(i + 2, 1, &["I", "E", "H"]) && !word.contains(i + 2, 2, &["HU"]) {
+        // "Accident" and "Succeed" against "Bacci" and "Bellocchio"
+        if (i == 1 && word.at(i - 1) == Some('A')) || word.contains(i - 1, 5, &["UCCEE", "UCCES"]) {
+            code.push_both("KS");
+        } else {
+            code.push_both("X");
+        }
+        i + 3
+    } else {
+        code.push_both("K");
+        i + 2
+    }
+}
+
+fn handle_ch(word: &Word, code: &mut Code, i: isize) -> isize {
+    if i > 0 && word.contains(i, 4, &["CHAE"]) {
+        // "Michael"
+        code.push("K", "X");
+    } else if condition_ch0(word, i) || condition_ch1(word, i) {
+        // Greek roots and Germanic names, as in "Chemistry" and "Schmidt"
+        code.push_both("K");
+    } else if i > 0 {
+        if word.contains(0, 2, &["MC"]) {
+            code.push_both("K");
+        } else {
+            code.push("X", "K");
+        }
+    } else {
+        code.push_both("X");
+    }
+    i + 2
+}
+
+fn condition_ch0(word: &Word, i: isize) -> bool {
+    i == 0
+        && (word.contains(i + 1, 5, &["HARAC", "HARIS"])
+            || word.contains(i + 1, 3, &["HOR", "HYM", "HIA", "HEM"]))
+        && !word.contains(0, 5, &["CHORE"])
+}
+
+fn condition_ch1(word: &Word, i: isize) -> bool {
+    word.germanic_start()
+        || word.contains(i - 2, 6, &["ORCHES", "ARCHIT", "ORCHID"])
+        || word.contains(i + 2, 1, &["T", "S"])
+        || ((word.contains(i - 1, 1, &["A", "O", "U", "E"]) || i == 0)
+            && (word.contains(
+                i + 2,
+                1,
+                &["L", "R", "N", "M", "B", "H", "F", "V", "W", " "],
+            ) || i + 1 == word.last() as isize))
+}
+
+fn handle_d(word: &Word, code: &mut Code, i: isize) -> isize {
+    if word.contains(i, 2, &["DG"]) {
+        if word.contains(i + 2, 1, &["I", "E", "Y"]) {
+            // "Edge"
+            code.push_both("J");
+            i + 3
+        } else {
+            // "Edgar"
+            code.push_both("TK");
+            i + 2
+        }
+    } else if word.contains(i, 2, &["DT", "DD"]) {
+        code.push_both("T");
+        i + 2
+    } else {
+        code.push_both("T");
+        i + 1
+    }
+}
+
+fn handle_g(word: &Word, code: &mut Code, i: isize) -> isize {
+    let slavo_germanic = word.slavo_germanic;
+    if word.at(i + 1) == Some('H') {
+        handle_gh(word, code, i)
+    } else if word.at(i + 1) == Some('N') {
+        if i == 1 && word.is_vowel(0) && !slavo_germanic {
+            code.push("KN", "N");
+        } else if !word.contains(i + 2, 2, &["EY"])
+            && word.at(i + 1) != Some('Y')
+            && !slavo_germanic
+        {
+            code.push("N", "KN");
+        } else {
+            code.push_both("KN");
+        }
+        i + 2
+    } else if word.contains(i + 1, 2, &["LI"]) && !slavo_germanic {
+        // "Tagliaro"
+        code.push("KL", "L");
+        i + 2
+    } else if (i == 0
+        && (word.at(i + 1) == Some('Y')
+            || word.contains(
+                i + 1,
+                2,
+                &[
+                    "ES", "EP", "EB", "EL", "EY", "IB", "IL", "IN", "IE", "EI", "ER",
+                ],
+            )))
+        || ((word.contains(i + 1, 2, &["ER"]) || word.at(i + 1) == Some('Y'))
+            && !word.contains(0, 6, &["DANGER", "RANGER", "MANGER"])
+            && !word.contains(i - 1, 1, &["E", "I"])
+            && !word.contains(i - 1, 3, &["RGY", "OGY"]))
+    {
+        // Initial "GE" and "GI", as in "Gerald", and "GER" or "GY" elsewhere
+        code.push("K", "J");
+        i + 2
+    } else if word.contains(i + 1, 1, &["E", "I", "Y"])
+        || word.contains(i - 1, 4, &["AGGI", "OGGI"])
+    {
+        if word.germanic_start() || word.contains(i + 1, 2, &["ET"]) {
+            code.push_both("K");
+        } else if word.contains(i + 1, 3, &["IER"]) {
+            code.push_both("J");
+        } else {
+            code.push("J", "K");
+        }
+        i + 2
+    } else if word.at(i + 1) == Some('G') {
+        code.push_both("K");
+        i + 2
+    } else {
+        code.push_both("K");
+        i + 1
+    }
+}
+
+fn handle_gh(word: &Word, code: &mut Code, i: isize) -> isize {
+    if i > 0 && !word.is_vowel(i - 1) {
+        code.push_both("K");
+    } else if i == 0 {
+        // "Ghislane" against "Ghiradelli"
+        if word.at(i + 2) == Some('I') {
+            code.push_both("J");
+        } else {
+            code.push_both("K");
+        }
+    } else if (i > 1 && word.contains(i - 2, 1, &["B", "H", "D"]))
+        || (i > 2 && word.contains(i - 3, 1, &["B", "H", "D"]))
+        || (i > 3 && word.contains(i - 4, 1, &["B", "H"]))
+    {
+        // Silent, as in "Hugh" and "Bough"
+    } else if i > 2
+        && word.at(i - 1) == Some('U')
+        && word.contains(i - 3, 1, &["C", "G", "L", "R", "T"])
+    {
+        // "Laugh", "Cough" and "Tough"
+        code.push_both("F");
+    } else if i > 0 && word.at(i - 1) != Some('I') {
+        code.push_both("K");
+    }
+    i + 2
+}
+
+fn handle_j(word: &Word, code: &mut Code, i: isize) -> isize {
+    let slavo_germanic = word.slavo_germanic;
+    if word.contains(i, 4, &["JOSE"]) || word.contains(0, 4, &["SAN "]) {
+        // Spanish "Jose" and "San Jacinto"
+        if (i == 0 && word.at(i + 4) == Some(' '))
+            || word.len() == 4
+            || word.contains(0, 4, &["SAN "])
+        {
+            code.push_both("H");
+        } else {
+            code.push("J", "H");
+        }
+        return i + 1;
+    }
+
+    if i == 0 {
+        code.push("J", "A");
+    } else if word.is_vowel(i - 1) && !slavo_germanic && matches!(word.at(i + 1), Some('A' | 'O')) {
+        code.push("J", "H");
+    } else if i == word.last() as isize {
+        code.push_primary("J");
+    } else if !word.contains(i + 1, 1, &["L", "T", "K", "S", "N", "M", "B", "Z"])
+        && !word.contains(i - 1, 1, &["S", "K", "L"])
+    {
+        code.push_both("J");
+    }
+
+    if word.at(i + 1) == Some('J') {
+        i + 2
+    } else {
+        i + 1
+    }
+}
+
+fn handle_l(word: &Word, code: &mut Code, i: isize) -> isize {
+    if word.at(i + 1) != Some('L') {
+        code.push_both("L");
+        return i + 1;
+    }
+
+    // Spanish "LL", as in "Cabrillo" and "Gallegos"
+    let n = word.len() as isize;
+    let spanish = (i == n - 3 && word.contains(i - 1, 4, &["ILLO", "ILLA", "ALLE"]))
+        || ((word.contains(n - 2, 2, &["AS", "OS"]) || word.contains(n - 1, 1, &["A", "O"]))
+            && word.contains(i - 1, 4, &["ALLE"]));
+    if spanish {
+        code.push_primary("L");
+    } else {
+        code.push_both("L");
+    }
+    i + 2
+}
+
+fn handle_s(word: &Word, code: &mut Code, i: isize) -> isize {
+    let slavo_germanic = word.slavo_germanic;
+    if word.contains(i - 1, 3, &["ISL", "YSL"]) {
+        // Silent in "Island" and "Carlysle"
+        i + 1
+    } else if i == 0 && word.contains(i, 5, &["SUGAR"]) {
+        code.push("X", "S");
+        i + 1
+    } else if word.contains(i, 2, &["SH"]) {
+        // Germanic "Holm" and "Holz"
+        if word.contains(i + 1, 4, &["HEIM", "HOEK", "HOLM", "HOLZ"]) {
+            code.push_both("S");
+        } else {
+            code.push_both("X");
+        }
+        i + 2
+    } else if word.contains(i, 3, &["SIO", "SIA"]) || word.contains(i, 4, &["SIAN"]) {
+        // Italian and Armenian
+        if slavo_germanic {
+            code.push_both("S");
+        } else {
+            code.push("S", "X");
+        }
+        i + 3
+    } else if (i == 0 && word.contains(i + 1, 1, &["M", "N", "L", "W"]))
+        || word.contains(i + 1, 1, &["Z"])
+    {
+        // German and anglicisations, as in "Smith" and "Schmidt"
+        code.push("S", "X");
+        if word.contains(i + 1, 1, &["Z"]) {
+            i + 2
+        } else {
+            i + 1
+        }
+    } else if word.contains(i, 2, &["SC"]) {
+        handle_sc(word, code, i)
+    } else {
+        // French final "AIS" and "OIS"
+        if i == word.last() as isize && word.contains(i - 2, 2, &["AI", "OI"]) {
+            code.push_alternate("S");
+        } else {
+            code.push_both("S");
+        }
+        if word.contains(i + 1, 1, &["S", "Z"]) {
+            i + 2
+        } else {
+            i + 1
+        }
+    }
+}
+
+fn handle_sc(word: &Word, code: &mut Code, i: isize) -> isize {
+    if word.at(i + 2) == Some('H') {
+        if word.contains(i + 3, 2, &["OO", "ER", "EN", "UY", "ED", "EM"]) {
+            // Dutch "Schooner" and "Schermerhorn"
+            if word.contains(i + 3, 2, &["ER", "EN"]) {
+                code.push("X", "SK");
+            } else {
+                code.push_both("SK");
+            }
+        } else if i == 0 && !word.is_vowel(3) && word.at(3) != Some('W') {
+            code.push("X", "S");
+        } else {
+            code.push_both("X");
+        }
+    } else if word.contains(i + 2, 1, &["I", "E", "Y"]) {
+        code.push_both("S");
+    } else {
+        code.push_both("SK");
+    }
+    i + 3
+}
+
+fn handle_t(word: &Word, code: &mut Code, i: isize) -> isize {
+    if word.contains(i, 4, &["TION"]) || word.contains(i, 3, &["TIA", "TCH"]) {
+        code.push_both("X");
+        i + 3
+    } else if word.contains(i, 2, &["TH"]) || word.contains(i, 3, &["TTH"]) {
+        // "Thomas" and "Thames"
+        if word.contains(i + 2, 2, &["OM", "AM"]) || word.germanic_start() {
+            code.push_both("T");
+        } else {
+            code.push("0", "T");
+        }
+        i + 2
+    } else {
+        code.push_both("T");
+        if word.contains(i + 1, 1, &["T", "D"]) {
+            i + 2
+        } else {
+            i + 1
+        }
+    }
+}
+
+fn handle_w(word: &Word, code: &mut Code, i: isize) -> isize {
+    if word.contains(i, 2, &["WR"]) {
+        code.push_both("R");
+        return i + 2;
+    }
+
+    if i == 0 && (word.is_vowel(i + 1) || word.contains(i, 2, &["WH"])) {
+        // "Wasserman" may also be read "Vasserman"
+        if word.is_vowel(i + 1) {
+            code.push("A", "F");
+        } else {
+            code.push_both("A");
+        }
+        i + 1
+    } else if (i == word.last() as isize && word.is_vowel(i - 1))
+        || word.contains(i - 1, 5, &["EWSKI", "EWSKY", "OWSKI", "OWSKY"])
+        || word.contains(0, 3, &["SCH"])
+    {
+        // Polish "Filipowicz" against English "Arnow"
+        code.push_alternate("F");
+        i + 1
+    } else if word.contains(i, 4, &["WICZ", "WITZ"]) {
+        code.push("TS", "FX");
+        i + 4
+    } else {
+        i + 1
+    }
+}
+
+fn handle_z(word: &Word, code: &mut Code, i: isize) -> isize {
+    if word.at(i + 1) == Some('H') {
+        // Chinese pinyin, as in "Zhao"
+        code.push_both("J");
+        i + 2
+    } else {
+        if word.contains(i + 1, 2, &["ZO", "ZI", "ZA"])
+            || (word.slavo_germanic && i > 0 && word.at(i - 1) != Some('T'))
+        {
+            code.push("S", "TS");
+        } else {
+            code.push_both("S");
+        }
+        if word.at(i + 1) == Some('Z') {
+            i + 2
+        } else {
+            i + 1
+        }
+    }
+}
@@ -0,0 +1,159 @@
+use crate::string::phonetic::{Codes, PhoneticEncoder};
+
+// Codes are cut to four characters, like Soundex codes
+const MAX_CODE_LEN: usize = 4;
+
+// Lawrence Philips' original Metaphone, following the rules of the widely
+// used Apache Commons Codec implementation
+pub struct Metaphone;
+impl PhoneticEncoder for Metaphone {
+    fn encode(&self, s: &str) -> Codes {
+        vec![metaphone(s)]
+    }
+}
+
+fn is_vowel(c: Option<char>) -> bool {
+    matches!(c, Some('A' | 'E' | 'I' | 'O' | 'U'))
+}
+
+fn is_front_vowel(c: Option<char>) -> bool {
+    matches!(c, Some('E' | 'I' | 'Y'))
+}
+
+pub fn metaphone(s: &str) -> String {
+    let word: Vec<char> = s
+        .chars()
+        .filter(|c| c.is_ascii_alphabetic())
+        .map(|c| c.to_ascii_uppercase())
+        .collect();
+    if word.len() <= 1 {
+        return word.into_iter().collect();
+    }
+
+    // Silent or altered initial letters
+    let word: Vec<char> = match (word[0], word[1]) {
+        ('K' | 'G' | 'P', 'N') | ('A', 'E') | ('W', 'R') => word[1..].to_vec(),
+        ('W', 'H') => [&['W'], &word[2..]].concat(),
+        ('X', _) => [&['S'], &word[1..]].concat(),
+        _ => word,
+    };
+
+    let at = |i: usize| word.get(i).copied();
+    let matches_at = |i: usize, pattern: &str| {
+        pattern
+            .chars()
+            .enumerate()
+            .all(|(offset, c)| at(i + offset) == Some(c))
+    };
+    let last = word.len() - 1;
+
+    let mut code = String::new();
+    let mut n = 0;
+    while code.len() < MAX_CODE_LEN && n < word.len() {
+        let c = word[n];
+        let prev = n.checked_sub(1).and_then(at);
+        let next = at(n + 1);
+
+        // Doubled letters sound once, except for C
+        if c != 'C' && prev == Some(c) {
+            n += 1;
+            continue;
+        }
+
+        match c {
+            'A' | 'E' | 'I' | 'O' | 'U' => {
+                if n == 0 {
+                    code.push(c);
+                }
+            }
+            // Silent in a final "MB"
+            'B' => {
+                if !(prev == Some('M') && n == last) {
+                    code.push('B');
+                }
+            }
+            'C' => {
+                if prev == Some('S') && is_front_vowel(next) {
+                    // Silent in "SCI", "SCE" and "SCY"
+                } else if matches_at(n, "CIA") {
+                    code.push('X');
+                } else if is_front_vowel(next) {
+                    code.push('S');
+                } else if prev == Some('S') && next == Some('H') {
+                    code.push('K');
+                } else if next == Some('H') {
+                    if n == 0 && word.len() >= 3 && is_vowel(at(2)) {
+                        code.push('K');
+                    } else {
+                        code.push('X');
+                    }
+                } else {
+                    code.push('K');
+                }
+            }
+            'D' => {
+                if next == Some('G') && is_front_vowel(at(n + 2)) {
+                    code.push('J');
+                    n += 2;
+                } else {
+                    code.push('T');
+                }
+            }
+            'G' => {
+                if next == Some('H') && (n + 1 == last || !is_vowel(at(n + 2))) {
+                    // Silent in "GH" before a consonant or at the end
+                } else if n > 0 && (matches_at(n, "GN") || matches_at(n, "GNED")) {
+                    // Silent in "GN" and "GNED"
+                } else if is_front_vowel(next) && prev != Some('G') {
+                    code.push('J');
+                } else {
+                    code.push('K');
+                }
+            }
+            'H' => {
+                let after_varson = matches!(prev, Some('C' | 'S' | 'P' | 'T' | 'G'));
+                if n < last && !after_varson && is_vowel(next) {
+                    code.push('H');
+                }
+            }
+            'K' => {
+                if prev != Some('C') {
+                    code.push('K');
+                }
+            }
+            'P' => code.push(if next == Some('H') { 'F' } else { 'P' }),
+            'Q' => code.push('K'),
+            'S' => {
+                if matches_at(n, "SH") || matches_at(n, "SIO") || matches_at(n, "SIA") {
+                    code.push('X');
+                } else {
+                    code.push('S');
+                }
+            }
+            'T' => {
+                if matches_at(n, "TIA") || matches_at(n, "TIO") {
+                    code.push('X');
+                } else if matches_at(n, "TCH") {
+                    // Silent before "CH"
+                } else if next == Some('H') {
+                    code.push('0');
+                } else {
+                    code.push('T');
+                }
+            }
+            'V' => code.push('F'),
+            'W' | 'Y' => {
+                if is_vowel(next) {
+                    code.push(c);
+                }
+            }
+            'X' => code.push_str("KS"),
+            'Z' => code.push('S'),
+            _ => code.push(c),
+        }
+        n += 1;
+    }
+
+    code.truncate(MAX_CODE_LEN);
+    code
+}
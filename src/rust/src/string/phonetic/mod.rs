@@ -0,0 +1,203 @@
+use crate::dedupe::Pairing;
+use crate::topk::{keep_best, Selection};
+use crate::utils::{robj_index_map, KeyColumn};
+use anyhow::Result;
+use extendr_api::prelude::*;
+use rayon::prelude::*;
+use rayon::ThreadPool;
+use rustc_hash::FxHashMap;
+
+pub mod caverphone;
+pub mod cologne;
+pub mod daitch_mokotoff;
+pub mod double_metaphone;
+pub mod metaphone;
+pub mod mra;
+pub mod nysiis;
+pub mod soundex;
+
+// The primary code first, then any alternates, for names that can be read
+// more than one way
+pub type Codes = Vec<String>;
+
+// Two keys match when they share any code
+pub fn shares_code(codes1: &Codes, codes2: &Codes) -> bool {
+    codes1.iter().any(|code| codes2.contains(code))
+}
+
+// Upper-case letters with umlauts and other diacritics folded to their base
+// letter, for encodings that only know the Latin alphabet. Other characters
+// are dropped.
+pub fn fold_letters(s: &str) -> Vec<char> {
+    s.chars()
+        .flat_map(char::to_uppercase)
+        .flat_map(|c| {
+            let folded: &[char] = match c {
+                'À' | 'Á' | 'Â' | 'Ã' | 'Ä' | 'Å' | 'Ą' => &['A'],
+                'Æ' => &['A', 'E'],
+                'Ç' | 'Ć' | 'Č' => &['C'],
+                'Ď' | 'Ð' => &['D'],
+                'È' | 'É' | 'Ê' | 'Ë' | 'Ę' | 'Ě' => &['E'],
+                'Ì' | 'Í' | 'Î' | 'Ï' => &['I'],
+                'Ł' => &['L'],
+                'Ñ' | 'Ń' | 'Ň' => &['N'],
+                'Ò' | 'Ó' | 'Ô' | 'Õ' | 'Ö' | 'Ø' => &['O'],
+                'Œ' => &['O', 'E'],
+                'Ř' => &['R'],
+                'Ś' | 'Š' => &['S'],
+                'ẞ' => &['S', 'S'],
+                'Ť' | 'Ţ' => &['T'],
+                'Ù' | 'Ú' | 'Û' | 'Ü' | 'Ů' => &['U'],
+                'Ý' | 'Ÿ' => &['Y'],
+                'Ź' | 'Ż' | 'Ž' => &['Z'],
+                _ => &[],
+            };
+            if folded.is_empty() && c.is_ascii_uppercase() {
+                vec![c]
+            } else {
+                folded.to_vec()
+            }
+        })
+        .collect()
+}
+
+// Define a trait for phonetic encodings. Matches are exact, so every pair
+// gets a distance of zero. Joins encode each distinct key once and, where
+// codes match by equality, bucket the right-hand keys by code so that each
+// left key only looks up its own codes.
+pub trait PhoneticEncoder: Send + Sync {
+    fn encode(&self, s: &str) -> Codes;
+
+    // Whether two encoded keys match. Encodings that compare codes by more
+    // than equality, like the Match Rating Approach, override this.
+    fn matches(&self, codes1: &Codes, codes2: &Codes) -> bool {
+        shares_code(codes1, codes2)
+    }
+
+    // Whether keys match exactly when they share a code, so that they can be
+    // bucketed by code. Encodings that override `matches` with a looser test
+    // return false and compare every pair of encoded keys instead.
+    fn hashable(&self) -> bool {
+        true
+    }
+
+    fn fuzzy_indices(
+        &self,
+        left: KeyColumn,
+        right: KeyColumn,
+        select: Selection,
+        pool: &ThreadPool,
+    ) -> Result<Vec<(usize, usize, f64)>> {
+        let map1 = robj_index_map(left.df, left.key)?;
+        let map2 = robj_index_map(right.df, right.key)?;
+
+        let idxs: Vec<(usize, usize, f64)> = pool.install(|| {
+            // Each distinct right-hand key is encoded once
+            let encoded: Vec<(&str, Codes, &Vec<usize>)> = map2
+                .par_iter()
+                .filter(|(k2, _)| !k2.is_na())
+                .map(|(k2, v2)| (*k2, self.encode(k2), v2))
+                .collect();
+
+            // Positions in `encoded` of the keys with each code
+            let mut buckets: FxHashMap<&str, Vec<usize>> = FxHashMap::default();
+            if self.hashable() {
+                for (i, (_, codes, _)) in encoded.iter().enumerate() {
+                    for code in codes {
+                        buckets.entry(code).or_default().push(i);
+                    }
+                }
+            }
+
+            map1.par_iter()
+                .filter_map(|(k1, v1)| {
+                    self.compare_one_to_many(k1, v1, &encoded, &buckets, select.pairing)
+                })
+                .map(|idxs| keep_best(idxs, select.top_k))
+                .flatten()
+                .collect()
+        });
+        Ok(idxs)
+    }
+
+    fn compare_pairs(
+        &self,
+        left: &Vec<&str>,
+        right: &Vec<&str>,
+        pool: &ThreadPool,
+    ) -> Result<(Vec<usize>, Vec<f64>)> {
+        let out = pool.install(|| {
+            // Keys repeat across pairs, so each distinct key is encoded once
+            let mut keys: Vec<&str> = left
+                .iter()
+                .chain(right)
+                .copied()
+                .filter(|k| !k.is_na())
+                .collect();
+            keys.par_sort_unstable();
+            keys.dedup();
+            let encoded: FxHashMap<&str, Codes> =
+                keys.into_par_iter().map(|k| (k, self.encode(k))).collect();
+
+            left.par_iter()
+                .zip(right)
+                .enumerate()
+                .filter_map(|(i, (l, r))| {
+                    if l.is_na() || r.is_na() {
+                        return None;
+                    }
+                    self.matches(&encoded[l], &encoded[r]).then_some((i, 0.))
+                })
+                .collect()
+        });
+        Ok(out)
+    }
+
+    fn compare_one_to_many(
+        &self,
+        k1: &str,
+        v1: &[usize],
+        encoded: &[(&str, Codes, &Vec<usize>)],
+        buckets: &FxHashMap<&str, Vec<usize>>,
+        pairing: Pairing,
+    ) -> Option<Vec<(usize, usize, f64)>> {
+        if k1.is_na() {
+            return None;
+        }
+
+        let codes1 = self.encode(k1);
+        let mut matched: Vec<usize> = if self.hashable() {
+            codes1
+                .iter()
+                .filter_map(|code| buckets.get(code.as_str()))
+                .flatten()
+                .copied()
+                .collect()
+        } else {
+            (0..encoded.len())
+                .filter(|&i| {
+                    pairing.compares_keys(k1, encoded[i].0) && self.matches(&codes1, &encoded[i].1)
+                })
+                .collect()
+        };
+
+        // A right-hand key sharing several codes is only matched once
+        matched.sort_unstable();
+        matched.dedup();
+
+        // A self-join pairs up each pair of keys once
+        let mut idxs: Vec<(usize, usize, f64)> = Vec::new();
+        for i in matched {
+            let (k2, _, v2) = encoded[i];
+            if pairing.compares_keys(k1, k2) {
+                idxs.extend(pairing.key_rows(v1, v2, 0.));
+            }
+        }
+
+        if idxs.is_empty() {
+            None
+        } else {
+            Some(idxs)
+        }
+    }
+}
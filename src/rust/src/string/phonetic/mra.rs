@@ -0,0 +1,85 @@
+use crate::string::phonetic::{Codes, PhoneticEncoder};
+
+// Codes keep their first three and last three letters
+const MAX_CODE_LEN: usize = 6;
+
+// Match Rating Approach (Western Airlines, 1977). Unlike the other phonetic
+// encodings, two codes match when they are similar enough rather than equal.
+pub struct MatchRating;
+impl PhoneticEncoder for MatchRating {
+    fn encode(&self, s: &str) -> Codes {
+        vec![mra_codex(s)]
+    }
+
+    fn matches(&self, codes1: &Codes, codes2: &Codes) -> bool {
+        mra_compare(&codes1[0], &codes2[0])
+    }
+
+    // Similar codes match too, so keys cannot be bucketed by code
+    fn hashable(&self) -> bool {
+        false
+    }
+}
+
+pub fn mra_codex(s: &str) -> String {
+    let letters: Vec<char> = s
+        .chars()
+        .filter(|c| c.is_ascii_alphabetic())
+        .map(|c| c.to_ascii_uppercase())
+        .collect();
+
+    // Vowels are dropped unless they come first, then doubled letters
+    let mut codex: Vec<char> = Vec::with_capacity(letters.len());
+    for (i, &c) in letters.iter().enumerate() {
+        if i > 0 && matches!(c, 'A' | 'E' | 'I' | 'O' | 'U') {
+            continue;
+        }
+        if codex.last() != Some(&c) {
+            codex.push(c);
+        }
+    }
+
+    if codex.len() > MAX_CODE_LEN {
+        codex.drain(3..codex.len() - 3);
+    }
+    codex.into_iter().collect()
+}
+
+// Lowest similarity that counts as a match, by the combined code length
+fn minimum_rating(total_len: usize) -> usize {
+    match total_len {
+        0..=4 => 5,
+        5..=7 => 4,
+        8..=11 => 3,
+        _ => 2,
+    }
+}
+
+// Letters left in the longer code after removing those that agree position
+// by position, first from the left and then from the right
+fn unmatched(a: &[char], b: &[char]) -> usize {
+    let n = a.len().min(b.len());
+    let (mut rest1, mut rest2): (Vec<char>, Vec<char>) = (0..n)
+        .filter(|&i| a[i] != b[i])
+        .map(|i| (a[i], b[i]))
+        .unzip();
+    rest1.extend(&a[n..]);
+    rest2.extend(&b[n..]);
+
+    let n = rest1.len().min(rest2.len());
+    let same = (1..=n)
+        .filter(|&j| rest1[rest1.len() - j] == rest2[rest2.len() - j])
+        .count();
+    rest1.len().max(rest2.len()) - same
+}
+
+pub fn mra_compare(codex1: &str, codex2: &str) -> bool {
+    let a: Vec<char> = codex1.chars().collect();
+    let b: Vec<char> = codex2.chars().collect();
+    if a.is_empty() || b.is_empty() || a.len().abs_diff(b.len()) >= 3 {
+        return false;
+    }
+
+    let similarity = MAX_CODE_LEN.saturating_sub(unmatched(&a, &b));
+    similarity >= minimum_rating(a.len() + b.len())
+}
@@ -0,0 +1,115 @@
+use crate::string::phonetic::{Codes, PhoneticEncoder};
+
+// The original algorithm keeps six characters
+const MAX_CODE_LEN: usize = 6;
+
+// New York State Identification and Intelligence System code, following the
+// rules of the Apache Commons Codec implementation
+pub struct Nysiis;
+impl PhoneticEncoder for Nysiis {
+    fn encode(&self, s: &str) -> Codes {
+        vec![nysiis(s)]
+    }
+}
+
+fn is_vowel(c: char) -> bool {
+    matches!(c, 'A' | 'E' | 'I' | 'O' | 'U')
+}
+
+// Replacement for the letter `curr`, which may also overwrite the letters
+// after it
+fn transcode(prev: char, curr: char, next: Option<char>, after_next: Option<char>) -> Vec<char> {
+    match (curr, next, after_next) {
+        ('E', Some('V'), _) => vec!['A', 'F'],
+        (c, _, _) if is_vowel(c) => vec!['A'],
+        ('Q', _, _) => vec!['G'],
+        ('Z', _, _) => vec!['S'],
+        ('M', _, _) => vec!['N'],
+        ('K', Some('N'), _) => vec!['N', 'N'],
+        ('K', _, _) => vec!['C'],
+        ('S', Some('C'), Some('H')) => vec!['S', 'S', 'S'],
+        ('P', Some('H'), _) => vec!['F', 'F'],
+        // Silent H and W take the sound of the letter before them
+        ('H', _, _) if !is_vowel(prev) || !next.is_some_and(is_vowel) => vec![prev],
+        ('W', _, _) if is_vowel(prev) => vec![prev],
+        (c, _, _) => vec![c],
+    }
+}
+
+pub fn nysiis(s: &str) -> String {
+    let mut word: String = s
+        .chars()
+        .filter(|c| c.is_ascii_alphabetic())
+        .map(|c| c.to_ascii_uppercase())
+        .collect();
+    if word.is_empty() {
+        return word;
+    }
+
+    // Leading letters
+    for (from, to) in [
+        ("MAC", "MCC"),
+        ("KN", "NN"),
+        ("K", "C"),
+        ("PH", "FF"),
+        ("PF", "FF"),
+        ("SCH", "SSS"),
+    ] {
+        if let Some(rest) = word.strip_prefix(from) {
+            word = format!("{}{}", to, rest);
+            break;
+        }
+    }
+
+    // Trailing letters
+    for (from, to) in [
+        ("EE", "Y"),
+        ("IE", "Y"),
+        ("DT", "D"),
+        ("RT", "D"),
+        ("RD", "D"),
+        ("NT", "D"),
+        ("ND", "D"),
+    ] {
+        if let Some(rest) = word.strip_suffix(from) {
+            word = format!("{}{}", rest, to);
+            break;
+        }
+    }
+
+    // Letters are rewritten in place, so later rules see the replacements
+    let mut chars: Vec<char> = word.chars().collect();
+    let mut key = vec![chars[0]];
+    for i in 1..chars.len() {
+        let replacement = transcode(
+            chars[i - 1],
+            chars[i],
+            chars.get(i + 1).copied(),
+            chars.get(i + 2).copied(),
+        );
+        for (offset, c) in replacement.into_iter().enumerate() {
+            if let Some(slot) = chars.get_mut(i + offset) {
+                *slot = c;
+            }
+        }
+        if chars[i] != chars[i - 1] {
+            key.push(chars[i]);
+        }
+    }
+
+    // Trailing S, a final AY as Y, and a trailing A are dropped
+    if key.len() > 1 {
+        if key.last() == Some(&'S') {
+            key.pop();
+        }
+        if key.len() > 2 && key.ends_with(&['A', 'Y']) {
+            key.remove(key.len() - 2);
+        }
+        if key.last() == Some(&'A') {
+            key.pop();
+        }
+    }
+
+    key.truncate(MAX_CODE_LEN);
+    key.into_iter().collect()
+}
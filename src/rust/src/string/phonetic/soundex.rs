@@ -1,111 +1,17 @@
-use crate::utils::robj_index_map;
-use anyhow::Result;
-use extendr_api::prelude::*;
-use itertools::iproduct;
-use rayon::prelude::*;
-use rayon::ThreadPool;
-use rustc_hash::FxHashMap;
+use crate::string::phonetic::{fold_letters, Codes, PhoneticEncoder};
 
+// American Soundex, with an alternate code for surnames with a prefix such
+// as "Van" or "De"
 pub struct Soundex;
-impl Soundex {
-    pub fn fuzzy_indices(
-        &self,
-        df1: &List,
-        left_key: &str,
-        df2: &List,
-        right_key: &str,
-        pool: &ThreadPool,
-    ) -> Result<Vec<(usize, usize, f64)>> {
-        let map1 = robj_index_map(&df1, left_key)?;
-        let map2 = robj_index_map(&df2, right_key)?;
-
-        let idxs: Vec<(usize, usize, f64)> = pool.install(|| {
-            map1.par_iter()
-                .filter_map(|(k1, v1)| self.compare_one_to_many(k1, v1, &map2))
-                .flatten()
-                .collect()
-        });
-        Ok(idxs)
-    }
-
-    pub fn compare_pairs(
-        &self,
-        left: &Vec<&str>,
-        right: &Vec<&str>,
-        pool: &ThreadPool,
-    ) -> Result<(Vec<usize>, Vec<f64>)> {
-        let out = pool.install(|| {
-            left.par_iter()
-                .zip(right)
-                .enumerate()
-                .filter_map(|(i, (l, r))| {
-                    if l.is_na() || r.is_na() {
-                        return None;
-                    }
-
-                    let (sx_l, alt_l) = soundex_na_dual(l);
-                    let (sx_r, alt_r) = soundex_na_dual(r);
-
-                    if sx_l == sx_r
-                        || alt_l == Some(sx_r)
-                        || alt_r == Some(sx_l)
-                        || (alt_l.is_some() && alt_r.is_some() && alt_l == alt_r)
-                    {
-                        Some((i, 0.))
-                    } else {
-                        None
-                    }
-                })
-                .collect()
-        });
-        Ok(out)
-    }
-
-    fn compare_one_to_many(
-        &self,
-        k1: &str,
-        v1: &Vec<usize>,
-        idx_map: &FxHashMap<&str, Vec<usize>>,
-    ) -> Option<Vec<(usize, usize, f64)>> {
-        if k1.is_na() {
-            return None;
-        }
-
-        let mut idxs: Vec<(usize, usize, f64)> = Vec::new();
-        let (sx1, alt1) = soundex_na_dual(k1);
-        let sx1 = sx1.as_str();
-
-        for (k2, v2) in idx_map.iter() {
-            if k2.is_na() {
-                continue;
-            }
-
-            let (sx2, alt2) = soundex_na_dual(k2);
-
-            if sx1 == sx2
-                || alt1 == Some(sx2)
-                || alt2 == Some(sx1.to_string())
-                || (alt1.is_some() && alt2.is_some() && alt1 == alt2)
-            {
-                iproduct!(v1, v2).for_each(|(a, b)| {
-                    idxs.push((*a, *b, 0.));
-                });
-            }
-        }
-
-        if idxs.is_empty() {
-            None
-        } else {
-            Some(idxs)
-        }
+impl PhoneticEncoder for Soundex {
+    fn encode(&self, s: &str) -> Codes {
+        let (code, alt) = soundex_na_dual(s);
+        std::iter::once(code).chain(alt).collect()
     }
 }
 
 pub fn soundex_na(s: &str) -> String {
-    let mut chars = s
-        .chars()
-        .filter(|c| c.is_ascii_alphabetic())
-        .map(|c| c.to_ascii_uppercase());
+    let mut chars = fold_letters(s).into_iter();
 
     let first_letter = match chars.next() {
         Some(c) => c,
@@ -155,9 +61,21 @@ pub fn soundex_na_dual(name: &str) -> (String, Option<String>) {
         "DE", "LA", "LE", "VAN", "VON", "DI", "O", "CON", "BIN", "ABU", "AL", "SAN", "SANTA",
     ];
 
-    // Clean and normalize
+    // Clean and normalize. Letters such as "ü" and "ß" are folded to ASCII,
+    // keeping their case for the camel-case split.
     let cleaned = name
         .chars()
+        .flat_map(|c| {
+            if c.is_ascii() || c.is_whitespace() {
+                return vec![c];
+            }
+            let folded = fold_letters(&c.to_string()).into_iter();
+            if c.is_lowercase() {
+                folded.map(|f| f.to_ascii_lowercase()).collect()
+            } else {
+                folded.collect()
+            }
+        })
         .filter(|c| c.is_ascii_alphabetic() || c.is_whitespace())
         .collect::<String>();
 
@@ -0,0 +1,134 @@
+// Best-match selection. Instead of every pair under `max_distance`, a join
+// can keep only the `k` closest right rows for each left row. Single-column
+// joins select on the matches of one left key (or left row) inside the
+// parallel candidate loops, so the full set of pairs is never materialised.
+// Multi-column joins select once every column has been checked.
+
+use crate::dedupe::Pairing;
+use anyhow::{anyhow, Result};
+use itertools::multizip;
+use rustc_hash::FxHashSet;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Ties {
+    // Keep every right row tied with the k-th best distance
+    All,
+    // Keep exactly `k` rows, preferring earlier right rows on ties
+    First,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct TopK {
+    pub k: usize,
+    pub ties: Ties,
+}
+
+impl TopK {
+    // Returns `None` when no `k` was given, i.e. a plain threshold join
+    pub fn new(k: Option<i32>, ties: &str) -> Result<Option<Self>> {
+        let Some(k) = k else {
+            return Ok(None);
+        };
+
+        let k = usize::try_from(k)
+            .ok()
+            .filter(|&k| k > 0)
+            .ok_or_else(|| anyhow!("`k` must be a positive integer"))?;
+
+        let ties = match ties {
+            "all" => Ties::All,
+            "first" => Ties::First,
+            _ => return Err(anyhow!("`ties` must be one of `all` or `first`")),
+        };
+
+        Ok(Some(TopK { k, ties }))
+    }
+
+    // Keep the best matches of every left row in `matches`, which may hold
+    // several left rows (e.g. all rows sharing a string key)
+    pub fn select(&self, mut matches: Vec<(usize, usize, f64)>) -> Vec<(usize, usize, f64)> {
+        matches.sort_by(|a, b| a.0.cmp(&b.0).then(a.2.total_cmp(&b.2)).then(a.1.cmp(&b.1)));
+
+        let mut out = Vec::with_capacity(matches.len().min(self.k));
+        let mut kept = 0;
+        let mut last: Option<(usize, f64)> = None;
+
+        for (i, j, d) in matches {
+            if last.is_some_and(|(row, _)| row != i) {
+                kept = 0;
+                last = None;
+            }
+
+            let keep = kept < self.k
+                || (self.ties == Ties::All && last.is_some_and(|(_, worst)| worst == d));
+
+            if keep {
+                out.push((i, j, d));
+                kept += 1;
+                last = Some((i, d));
+            }
+        }
+
+        out
+    }
+}
+
+// What a kernel keeps of the candidate pairs it finds: the best matches of
+// each left row when `top_k` is set, with rows paired up as `pairing` says
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Selection<'a> {
+    pub top_k: Option<&'a TopK>,
+    pub pairing: Pairing,
+}
+
+impl<'a> Selection<'a> {
+    pub fn top_k(top_k: Option<&'a TopK>) -> Self {
+        Selection {
+            top_k,
+            pairing: Pairing::Cross,
+        }
+    }
+
+    pub fn self_join() -> Self {
+        Selection {
+            top_k: None,
+            pairing: Pairing::SelfJoin,
+        }
+    }
+}
+
+// Apply `top_k`, if any, to the matches of one unit of parallel work
+pub fn keep_best(
+    matches: Vec<(usize, usize, f64)>,
+    top_k: Option<&TopK>,
+) -> Vec<(usize, usize, f64)> {
+    match top_k {
+        Some(top_k) => top_k.select(matches),
+        None => matches,
+    }
+}
+
+// Positions of the pairs to keep when a multi-column join has already been
+// filtered on every column. Pairs are ranked by `dists`, the distance on the
+// first column.
+pub fn keep_best_rows(idxs1: &[usize], idxs2: &[usize], dists: &[f64], top_k: &TopK) -> Vec<usize> {
+    let matches = multizip((
+        idxs1.iter().copied(),
+        idxs2.iter().copied(),
+        dists.iter().copied(),
+    ))
+    .collect();
+    let kept: FxHashSet<(usize, usize)> = top_k
+        .select(matches)
+        .into_iter()
+        .map(|(i, j, _)| (i, j))
+        .collect();
+
+    idxs1
+        .iter()
+        .zip(idxs2)
+        .enumerate()
+        .filter(|(_, (i, j))| kept.contains(&(**i, **j)))
+        .map(|(pos, _)| pos)
+        .collect()
+}
@@ -4,6 +4,13 @@ use rayon::ThreadPool;
 use rayon::ThreadPoolBuilder;
 use rustc_hash::FxHashMap;
 
+// The key column of one side of a join
+#[derive(Clone, Copy)]
+pub struct KeyColumn<'a> {
+    pub df: &'a List,
+    pub key: &'a str,
+}
+
 pub fn robj_index_map<'a>(df: &'a List, key: &'a str) -> Result<FxHashMap<&'a str, Vec<usize>>> {
     let mut map: FxHashMap<&str, Vec<usize>> = FxHashMap::default();
 
@@ -113,21 +120,90 @@ pub fn get_pool(nthread: Option<usize>) -> Result<ThreadPool> {
     }
 }
 
-pub fn any_numeric_to_vec64(df: &List, key: &str) -> Result<Vec<f64>> {
-    let df_col = df
+// Values of a numeric join column as doubles, with missing values as NaN.
+// Integer, double and logical columns are accepted, and so are Date and
+// POSIXct, which are numbers with a class. `side` names the table in errors.
+pub fn any_numeric_to_vec64(df: &List, key: &str, side: &str) -> Result<Vec<f64>> {
+    let col = df
         .dollar(key)
-        .map_err(|_| anyhow!("Column `{}` not found in df1", key))?;
+        .map_err(|_| anyhow!("Column `{}` not found in {}", key, side))?;
 
-    let df_vals: Vec<f64> = if let Some(v) = df_col.as_real_vector() {
-        v.to_vec()
-    } else if let Some(v) = df_col.as_integer_vector() {
-        v.iter().map(|&x| x as f64).collect()
-    } else {
+    // Factor codes are integers, but their order means nothing
+    if col.inherits("factor") {
         return Err(anyhow!(
-            "Column `{}` in df1 is not numeric (integer or double)",
-            key
+            "Column `{}` in {} is a factor, not numeric",
+            key,
+            side
         ));
-    };
+    }
+
+    match col.rtype() {
+        Rtype::Doubles => Ok(col.as_real_slice().unwrap_or_default().to_vec()),
+        Rtype::Integers => Ok(col
+            .as_integer_slice()
+            .unwrap_or_default()
+            .iter()
+            .map(|i| if i.is_na() { f64::NAN } else { *i as f64 })
+            .collect()),
+        Rtype::Logicals => Ok(col
+            .as_logical_slice()
+            .unwrap_or_default()
+            .iter()
+            .map(|b| {
+                if b.is_na() {
+                    f64::NAN
+                } else {
+                    b.is_true() as u8 as f64
+                }
+            })
+            .collect()),
+        // Point at the first value that cannot be read as a number
+        Rtype::Strings => {
+            let bad = col
+                .as_str_iter()
+                .and_then(|mut iter| iter.position(|s| !s.is_na()));
+            match bad {
+                Some(i) => Err(anyhow!(
+                    "Column `{}` in {} is not numeric: row {} is a string",
+                    key,
+                    side,
+                    i + 1
+                )),
+                None => Err(anyhow!("Column `{}` in {} is not numeric", key, side)),
+            }
+        }
+        Rtype::List if col.inherits("POSIXlt") => Err(anyhow!(
+            "Column `{}` in {} is POSIXlt; convert it with `as.POSIXct()`",
+            key,
+            side
+        )),
+        rtype => Err(anyhow!(
+            "Column `{}` in {} is not numeric ({:?})",
+            key,
+            side,
+            rtype
+        )),
+    }
+}
+
+// Keep only the pairs at positions `keep`, along with their distances
+pub fn subset_pairs(
+    idxs1: &[usize],
+    idxs2: &[usize],
+    dists: &[Vec<f64>],
+    keep: &[usize],
+) -> (Vec<usize>, Vec<usize>, Vec<Vec<f64>>) {
+    (
+        keep.iter().map(|&p| idxs1[p]).collect(),
+        keep.iter().map(|&p| idxs2[p]).collect(),
+        dists
+            .iter()
+            .map(|d| keep.iter().map(|&p| d[p]).collect())
+            .collect(),
+    )
+}
 
-    Ok(df_vals)
+// Number of rows of a data frame, taken from its first column
+pub fn df_nrows(df: &List) -> usize {
+    df.values().next().map_or(0, |col| col.len())
 }